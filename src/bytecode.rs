@@ -0,0 +1,419 @@
+//! A compact binary encoding of optimized instructions
+//!
+//! A file starts with the magic bytes `BFC`, a version byte and the number
+//! of instructions as a little-endian `u32`. Each instruction is a tag byte
+//! followed by its operands in little-endian order: bracket targets as `u32`,
+//! `Add` and `AddAt` amounts and `MulAdd` factors as `i32`, offsets as
+//! `i64` of at most `MAX_LEN` either way, `ClearRange` lengths and
+//! `WriteRepeat` counts as `u64` of at most `MAX_LEN` and `SetValue` values
+//! as `u32`.
+//! A `Comment` is its length as a `u32` followed by its bytes.
+//!
+//! The version goes up whenever a tag is added or an encoding changes, and
+//! `decode` only reads its own version, since an older file may use tags
+//! that meant something else when it was written.
+//!
+//! A cache directory holds bytecode named by a hash of the source and the
+//! settings that affect how it compiles, so a program run again with the
+//! same settings skips parsing and optimizing.
+
+use crate::{validate_ops, BfError, BfOp, Config};
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use BfOp::*;
+
+const MAGIC: &[u8] = b"BFC";
+const VERSION: u8 = 3;
+
+/// The longest `ClearRange` or `WriteRepeat` `decode` accepts, so hostile
+/// bytecode can't ask for unbounded memory or output in one instruction,
+/// and the furthest offset in either direction
+pub const MAX_LEN: usize = u32::MAX as usize;
+
+/// Serializes instructions to bytecode
+pub fn encode(instrs: &[BfOp]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend(&(instrs.len() as u32).to_le_bytes());
+    for op in instrs {
+        match *op {
+            Gt => out.push(0),
+            Lt => out.push(1),
+            Plus => out.push(2),
+            Minus => out.push(3),
+            Dot => out.push(4),
+            Comma => out.push(5),
+            LBracket(i) => {
+                out.push(6);
+                out.extend(&(i as u32).to_le_bytes());
+            }
+            LoopStart(i) => {
+                out.push(22);
+                out.extend(&(i as u32).to_le_bytes());
+            }
+            RBracket(i) => {
+                out.push(7);
+                out.extend(&(i as u32).to_le_bytes());
+            }
+            Add(n) => {
+                out.push(8);
+                out.extend(&n.to_le_bytes());
+            }
+            Move(n) => {
+                out.push(9);
+                out.extend(&(n as i64).to_le_bytes());
+            }
+            Clear => out.push(10),
+            Debug => out.push(11),
+            ScanRight => out.push(12),
+            ScanLeft => out.push(13),
+            MulAdd { offset, factor } => {
+                out.push(14);
+                out.extend(&(offset as i64).to_le_bytes());
+                out.extend(&factor.to_le_bytes());
+            }
+            Halt => out.push(15),
+            PrintInt => out.push(17),
+            Random => out.push(19),
+            Reset => out.push(20),
+            SetValue(n) => {
+                out.push(23);
+                out.extend(&n.to_le_bytes());
+            }
+            WriteRepeat { count } => {
+                out.push(24);
+                out.extend(&(count as u64).to_le_bytes());
+            }
+            ClearRange { from, len } => {
+                out.push(21);
+                out.extend(&(from as i64).to_le_bytes());
+                out.extend(&(len as u64).to_le_bytes());
+            }
+            AddAt { offset, val } => {
+                out.push(16);
+                out.extend(&(offset as i64).to_le_bytes());
+                out.extend(&val.to_le_bytes());
+            }
+            Comment(ref text) => {
+                out.push(18);
+                out.extend(&(text.len() as u32).to_le_bytes());
+                out.extend(text);
+            }
+        }
+    }
+    out
+}
+
+/// Reads fixed-size fields from the front of a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], BfError> {
+        if self.0.len() < N {
+            return Err(BfError::InvalidBytecode("unexpected end of file"));
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, BfError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BfError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, BfError> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, BfError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, BfError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    /// A `u64` length, which must be at most `MAX_LEN`
+    fn len(&mut self) -> Result<usize, BfError> {
+        match self.u64()? {
+            n if n <= MAX_LEN as u64 => Ok(n as usize),
+            _ => Err(BfError::InvalidBytecode("length out of range")),
+        }
+    }
+
+    /// An `i64` offset, which must be at most `MAX_LEN` either way
+    fn offset(&mut self) -> Result<isize, BfError> {
+        match self.i64()? {
+            n if n.unsigned_abs() <= MAX_LEN as u64 => Ok(n as isize),
+            _ => Err(BfError::InvalidBytecode("offset out of range")),
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<Vec<u8>, BfError> {
+        if self.0.len() < len {
+            return Err(BfError::InvalidBytecode("unexpected end of file"));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head.to_vec())
+    }
+}
+
+/// Deserializes bytecode, checking the header and bracket targets
+pub fn decode(bytes: &[u8]) -> Result<Vec<BfOp>, BfError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(BfError::InvalidBytecode("missing header"));
+    }
+    let mut r = Reader(&bytes[MAGIC.len()..]);
+    if r.u8()? != VERSION {
+        return Err(BfError::InvalidBytecode("unsupported version"));
+    }
+    let len = r.u32()? as usize;
+    let mut instrs = Vec::with_capacity(len.min(bytes.len()));
+    for _ in 0..len {
+        let op = match r.u8()? {
+            0 => Gt,
+            1 => Lt,
+            2 => Plus,
+            3 => Minus,
+            4 => Dot,
+            5 => Comma,
+            6 => LBracket(r.u32()? as usize),
+            7 => RBracket(r.u32()? as usize),
+            8 => Add(r.i32()?),
+            9 => Move(r.offset()?),
+            10 => Clear,
+            11 => Debug,
+            12 => ScanRight,
+            13 => ScanLeft,
+            14 => MulAdd {
+                offset: r.offset()?,
+                factor: r.i32()?,
+            },
+            15 => Halt,
+            17 => PrintInt,
+            19 => Random,
+            20 => Reset,
+            22 => LoopStart(r.u32()? as usize),
+            23 => SetValue(r.u32()?),
+            24 => WriteRepeat { count: r.len()? },
+            21 => ClearRange {
+                from: r.offset()?,
+                len: r.len()?,
+            },
+            16 => AddAt {
+                offset: r.offset()?,
+                val: r.i32()?,
+            },
+            18 => {
+                let len = r.u32()? as usize;
+                Comment(r.bytes(len)?)
+            }
+            _ => return Err(BfError::InvalidBytecode("unknown instruction")),
+        };
+        instrs.push(op);
+    }
+    if !r.0.is_empty() {
+        return Err(BfError::InvalidBytecode("trailing data"));
+    }
+    validate_ops(&instrs).map_err(|_| BfError::InvalidBytecode("mismatched brackets"))?;
+    Ok(instrs)
+}
+
+/// A 64-bit FNV-1a hash, which unlike `DefaultHasher` is the same across
+/// Rust versions
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &part in parts {
+        for &byte in part {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// The file in the cache directory `dir` for `code` compiled with `config`
+pub fn cache_path(dir: &Path, code: &[u8], config: &Config) -> PathBuf {
+    let settings = format!(
+        "{:?} {:?} {}",
+        config.parse_options(),
+        config.opt_level,
+        config.fold_io
+    );
+    let hash = fnv1a(&[&[VERSION], settings.as_bytes(), code]);
+    dir.join(format!("{:016x}.bfc", hash))
+}
+
+/// Reads the instructions cached in `dir` for `code` compiled with
+/// `config`, or `None` if there are none or the entry isn't valid bytecode
+/// of this version
+///
+/// ```
+/// use brainfuck::{bytecode, Config, Interpreter};
+/// use std::fs;
+///
+/// let dir = std::env::temp_dir().join(format!("bf-cache-{}", std::process::id()));
+/// let (code, config) = (b"++[>+++<-]>.", Config::default());
+/// assert_eq!(bytecode::read_cache(&dir, code, &config), None);
+///
+/// let mut interp = Interpreter::new(config.clone());
+/// interp.load(code).unwrap();
+/// bytecode::write_cache(&dir, code, &config, interp.instrs()).unwrap();
+/// assert!(bytecode::cache_path(&dir, code, &config).exists());
+/// let cached = bytecode::read_cache(&dir, code, &config);
+/// assert_eq!(cached.as_deref(), Some(interp.instrs()));
+///
+/// fs::write(bytecode::cache_path(&dir, code, &config), b"BFC\xff").unwrap();
+/// assert_eq!(bytecode::read_cache(&dir, code, &config), None);
+/// fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn read_cache(dir: &Path, code: &[u8], config: &Config) -> Option<Vec<BfOp>> {
+    let bytes = fs::read(cache_path(dir, code, config)).ok()?;
+    decode(&bytes).ok()
+}
+
+/// Caches the instructions compiled from `code` with `config` in `dir`,
+/// creating it if needed
+pub fn write_cache(dir: &Path, code: &[u8], config: &Config, instrs: &[BfOp]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = cache_path(dir, code, config);
+    // Renaming into place keeps other runs from reading a partial file
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, encode(instrs))?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::{fold_writes, optimize, OptLevel};
+    use crate::{parse_with, Config, Interpreter, ParseOptions};
+    use std::collections::BTreeSet;
+
+    /// Runs instructions on a fresh interpreter, returning the output
+    fn run(instrs: Vec<BfOp>) -> Vec<u8> {
+        let mut interp = Interpreter::new(Config::default());
+        interp.load_ops(instrs);
+        let mut out = Vec::new();
+        interp.run(&b"x"[..], &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let code = b"#,>+<-[->++<]>.[-]>>+[<]>[>]+[.-]>{7}>+>+[-]<[-]?&~@.. end";
+        let opts = ParseOptions {
+            debug: true,
+            extensions: true,
+            keep_comments: true,
+            ..ParseOptions::default()
+        };
+        let naive = parse_with(code, opts).unwrap();
+        let optimized = fold_writes(&optimize(naive.clone(), OptLevel::O2));
+        let tags: BTreeSet<u8> = naive
+            .iter()
+            .chain(&optimized)
+            .map(|op| encode(std::slice::from_ref(op))[MAGIC.len() + 5])
+            .collect();
+        assert_eq!(tags, (0..=24).collect());
+        for instrs in &[naive, optimized] {
+            let decoded = decode(&encode(instrs)).unwrap();
+            assert_eq!(&decoded, instrs);
+            assert_eq!(run(decoded), run(instrs.clone()));
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = encode(&[Plus, Comment(b"x".to_vec()), Dot]);
+        assert_eq!(decode(&bytes).unwrap()[1], Comment(b"x".to_vec()));
+        for version in [1, VERSION + 1] {
+            bytes[MAGIC.len()] = version;
+            assert!(matches!(
+                decode(&bytes),
+                Err(BfError::InvalidBytecode("unsupported version"))
+            ));
+        }
+    }
+
+    #[test]
+    fn huge_lengths_are_rejected() {
+        let ok = [
+            WriteRepeat { count: MAX_LEN },
+            ClearRange { from: 0, len: 3 },
+        ];
+        assert_eq!(decode(&encode(&ok)).unwrap(), ok);
+        let huge = [
+            WriteRepeat { count: 1 << 62 },
+            ClearRange {
+                from: 0,
+                len: MAX_LEN + 1,
+            },
+        ];
+        for op in huge {
+            assert!(matches!(
+                decode(&encode(&[op])),
+                Err(BfError::InvalidBytecode("length out of range"))
+            ));
+        }
+    }
+
+    #[test]
+    fn huge_offsets_are_rejected() {
+        let ok = [
+            Move(-(MAX_LEN as isize)),
+            AddAt {
+                offset: MAX_LEN as isize,
+                val: 1,
+            },
+        ];
+        assert_eq!(decode(&encode(&ok)).unwrap(), ok);
+        let far = MAX_LEN as isize + 1;
+        let huge = [
+            Move(far),
+            MulAdd {
+                offset: -far,
+                factor: 2,
+            },
+            ClearRange { from: far, len: 1 },
+            AddAt {
+                offset: isize::MIN,
+                val: 1,
+            },
+        ];
+        for op in huge {
+            assert!(matches!(
+                decode(&encode(&[op])),
+                Err(BfError::InvalidBytecode("offset out of range"))
+            ));
+        }
+    }
+
+    #[test]
+    fn offsets_past_the_end_of_isize_are_out_of_bounds() {
+        for op in [
+            Move(isize::MAX),
+            AddAt {
+                offset: isize::MAX,
+                val: 1,
+            },
+            ClearRange {
+                from: isize::MAX,
+                len: 1,
+            },
+        ] {
+            let mut interp = Interpreter::from_ops(Config::default(), vec![Gt, op]).unwrap();
+            assert!(matches!(
+                interp.run(io::empty(), io::sink()),
+                Err(BfError::PointerOutOfBounds { pc: 1, .. })
+            ));
+        }
+    }
+}
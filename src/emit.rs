@@ -0,0 +1,794 @@
+//! Transpilers from parsed brainfuck to other languages
+
+use crate::{line_col, BfOp, CellSize, Config, Eof};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Tape size used in generated code when the config leaves it unbounded
+pub(crate) const DEFAULT_TAPE_SIZE: usize = 1 << 16;
+
+use BfOp::*;
+
+/// Output language for `--emit`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Target {
+    C,
+    Rust,
+    Dot,
+    Wat,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" => Ok(Target::C),
+            "rust" => Ok(Target::Rust),
+            "dot" => Ok(Target::Dot),
+            "wat" => Ok(Target::Wat),
+            _ => Err(format!("Invalid emit target: {}", s)),
+        }
+    }
+}
+
+/// Formats the index expression of the cell at `offset` from the pointer
+fn index(offset: isize) -> String {
+    match offset {
+        0 => "ptr".to_string(),
+        n if n < 0 => format!("ptr - {}", -n),
+        n => format!("ptr + {}", n),
+    }
+}
+
+/// A language that structured brainfuck can be written in
+trait Backend {
+    fn header(&self, out: &mut String);
+    fn footer(&self, out: &mut String);
+    /// Statement for a single instruction other than a bracket or comment
+    fn op(&self, op: BfOp) -> String;
+    /// Opens a loop, which tests the current cell on entry if `guarded`
+    fn loop_start(&self, guarded: bool) -> String;
+    fn loop_end(&self, guarded: bool) -> String;
+    /// Comment naming the source position of the next statement
+    fn position(&self, line: usize, col: usize) -> String;
+}
+
+/// Writes the program with `backend`, rebuilding loops from the bracket
+/// offsets, and commenting each statement with its line and column in
+/// `positions` if given
+fn emit_with<B: Backend>(
+    backend: &B,
+    instrs: &[BfOp],
+    positions: Option<&[(usize, usize)]>,
+) -> String {
+    fn block<B: Backend>(
+        backend: &B,
+        instrs: &[BfOp],
+        positions: Option<&[(usize, usize)]>,
+        start: usize,
+        end: usize,
+        depth: usize,
+        out: &mut String,
+    ) {
+        let mut i = start;
+        while i < end {
+            if let Some(&(line, col)) = positions.and_then(|p| p.get(i)) {
+                if !matches!(instrs[i], Comment(_)) {
+                    let comment = backend.position(line, col);
+                    writeln!(out, "{:w$}{}", "", comment, w = depth * 4).unwrap();
+                }
+            }
+            match instrs[i] {
+                LBracket(j) | LoopStart(j) => {
+                    let guarded = matches!(instrs[i], LBracket(_));
+                    let start = backend.loop_start(guarded);
+                    writeln!(out, "{:w$}{}", "", start, w = depth * 4).unwrap();
+                    block(backend, instrs, positions, i + 1, j, depth + 1, out);
+                    let end = backend.loop_end(guarded);
+                    writeln!(out, "{:w$}{}", "", end, w = depth * 4).unwrap();
+                    i = j;
+                }
+                Comment(_) => {}
+                ref op => {
+                    let op = backend.op(op.clone());
+                    writeln!(out, "{:w$}{}", "", op, w = depth * 4).unwrap();
+                }
+            }
+            i += 1;
+        }
+    }
+    let mut out = String::new();
+    backend.header(&mut out);
+    block(backend, instrs, positions, 0, instrs.len(), 1, &mut out);
+    backend.footer(&mut out);
+    out
+}
+
+struct CBackend<'a> {
+    config: &'a Config,
+    /// Whether to declare the generator state for `Random`
+    random: bool,
+}
+
+impl<'a> CBackend<'a> {
+    fn new(instrs: &[BfOp], config: &'a Config) -> Self {
+        CBackend {
+            config,
+            random: uses_random(instrs),
+        }
+    }
+}
+
+impl Backend for CBackend<'_> {
+    fn header(&self, out: &mut String) {
+        let cell = match self.config.cell_size {
+            CellSize::U8 => "uint8_t",
+            CellSize::U16 => "uint16_t",
+            CellSize::U32 => "uint32_t",
+        };
+        writeln!(out, "#include <stdint.h>").unwrap();
+        writeln!(out, "#include <stdio.h>").unwrap();
+        writeln!(out, "#include <string.h>").unwrap();
+        writeln!(out).unwrap();
+        let size = self.config.tape_size.unwrap_or(DEFAULT_TAPE_SIZE);
+        writeln!(out, "{} tape[{}];", cell, size).unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "int main(void) {{").unwrap();
+        writeln!(out, "    size_t ptr = {};", self.config.start_offset).unwrap();
+        writeln!(out, "    int c;").unwrap();
+        if self.random {
+            writeln!(out, "    uint64_t rng = {}ULL, z;", self.config.seed).unwrap();
+        }
+    }
+
+    fn footer(&self, out: &mut String) {
+        writeln!(out, "    return 0;").unwrap();
+        writeln!(out, "}}").unwrap();
+    }
+
+    fn op(&self, op: BfOp) -> String {
+        match op {
+            Gt => "ptr++;",
+            Lt => "ptr--;",
+            Plus => "tape[ptr]++;",
+            Minus => "tape[ptr]--;",
+            Add(n) if n < 0 => return format!("tape[ptr] -= {};", -n),
+            Add(n) => return format!("tape[ptr] += {};", n),
+            Move(n) if n < 0 => return format!("ptr -= {};", -n),
+            Move(n) => return format!("ptr += {};", n),
+            Clear => "tape[ptr] = 0;",
+            SetValue(n) => return format!("tape[ptr] = {};", n & self.config.cell_size.mask()),
+            Debug => "/* # */",
+            ScanRight => "while (tape[ptr]) ptr++;",
+            ScanLeft => "while (tape[ptr]) ptr--;",
+            Halt => "return 0;",
+            PrintInt => "printf(\"%lu\", (unsigned long)tape[ptr]);",
+            // The interpreter's splitmix64 generator
+            Random => concat!(
+                "rng += 0x9e3779b97f4a7c15ULL; ",
+                "z = (rng ^ rng >> 30) * 0xbf58476d1ce4e5b9ULL; ",
+                "z = (z ^ z >> 27) * 0x94d049bb133111ebULL; ",
+                "tape[ptr] = (z ^ z >> 31) >> 56;"
+            ),
+            Reset => {
+                return format!(
+                    "memset(tape, 0, sizeof tape); ptr = {};",
+                    self.config.start_offset
+                )
+            }
+            ClearRange { from, len } => {
+                return format!("memset(&tape[{}], 0, {} * sizeof *tape);", index(from), len)
+            }
+            MulAdd { offset, factor: 1 } => {
+                return format!("tape[{}] += tape[ptr];", index(offset))
+            }
+            MulAdd { offset, factor: -1 } => {
+                return format!("tape[{}] -= tape[ptr];", index(offset))
+            }
+            MulAdd { offset, factor } => {
+                return format!("tape[{}] += tape[ptr] * {};", index(offset), factor)
+            }
+            AddAt { offset, val } if val < 0 => {
+                return format!("tape[{}] -= {};", index(offset), -val)
+            }
+            AddAt { offset, val } => return format!("tape[{}] += {};", index(offset), val),
+            Dot => "putchar(tape[ptr]);",
+            WriteRepeat { count } => {
+                return format!("for (int i = 0; i < {}; i++) putchar(tape[ptr]);", count)
+            }
+            Comma => match self.config.eof {
+                Eof::Zero => "c = getchar(); tape[ptr] = c == EOF ? 0 : c;",
+                Eof::MinusOne => "c = getchar(); tape[ptr] = c == EOF ? -1 : c;",
+                Eof::Unchanged => "c = getchar(); if (c != EOF) tape[ptr] = c;",
+            },
+            LBracket(_) | LoopStart(_) | RBracket(_) | Comment(_) => unreachable!(),
+        }
+        .to_string()
+    }
+
+    fn loop_start(&self, guarded: bool) -> String {
+        match guarded {
+            true => "while (tape[ptr]) {".to_string(),
+            false => "do {".to_string(),
+        }
+    }
+
+    fn loop_end(&self, guarded: bool) -> String {
+        match guarded {
+            true => "}".to_string(),
+            false => "} while (tape[ptr]);".to_string(),
+        }
+    }
+
+    fn position(&self, line: usize, col: usize) -> String {
+        format!("/* line {}, column {} */", line, col)
+    }
+}
+
+struct RustBackend<'a> {
+    config: &'a Config,
+    /// Whether to declare the generator state for `Random`
+    random: bool,
+}
+
+impl<'a> RustBackend<'a> {
+    fn new(instrs: &[BfOp], config: &'a Config) -> Self {
+        RustBackend {
+            config,
+            random: uses_random(instrs),
+        }
+    }
+
+    fn cell_type(&self) -> &'static str {
+        match self.config.cell_size {
+            CellSize::U8 => "u8",
+            CellSize::U16 => "u16",
+            CellSize::U32 => "u32",
+        }
+    }
+}
+
+impl Backend for RustBackend<'_> {
+    fn header(&self, out: &mut String) {
+        writeln!(out, "#![allow(unused)]").unwrap();
+        writeln!(out, "use std::io::{{self, Read, Write}};").unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "fn main() {{").unwrap();
+        let size = self.config.tape_size.unwrap_or(DEFAULT_TAPE_SIZE);
+        writeln!(
+            out,
+            "    let mut tape = vec![0{}; {}];",
+            self.cell_type(),
+            size
+        )
+        .unwrap();
+        writeln!(out, "    let mut ptr = {}usize;", self.config.start_offset).unwrap();
+        writeln!(out, "    let mut buf = [0u8; 1];").unwrap();
+        if self.random {
+            writeln!(out, "    let mut rng = {}u64;", self.config.seed).unwrap();
+        }
+        writeln!(out, "    let mut stdin = io::stdin();").unwrap();
+        writeln!(out, "    let stdout = io::stdout();").unwrap();
+        writeln!(out, "    let mut stdout = stdout.lock();").unwrap();
+    }
+
+    fn footer(&self, out: &mut String) {
+        writeln!(out, "    stdout.flush().unwrap();").unwrap();
+        writeln!(out, "}}").unwrap();
+    }
+
+    fn op(&self, op: BfOp) -> String {
+        let eof = match self.config.eof {
+            Eof::Zero => "tape[ptr] = 0; ".to_string(),
+            Eof::MinusOne => format!("tape[ptr] = {}::MAX; ", self.cell_type()),
+            Eof::Unchanged => String::new(),
+        };
+        match op {
+            Gt => "ptr += 1;".to_string(),
+            Lt => "ptr -= 1;".to_string(),
+            Plus => "tape[ptr] = tape[ptr].wrapping_add(1);".to_string(),
+            Minus => "tape[ptr] = tape[ptr].wrapping_sub(1);".to_string(),
+            Add(n) => format!(
+                "tape[ptr] = tape[ptr].wrapping_add({});",
+                n as u32 & self.config.cell_size.mask()
+            ),
+            Move(n) if n < 0 => format!("ptr -= {};", -n),
+            Move(n) => format!("ptr += {};", n),
+            Clear => "tape[ptr] = 0;".to_string(),
+            SetValue(n) => format!("tape[ptr] = {};", n & self.config.cell_size.mask()),
+            Debug => "// #".to_string(),
+            ScanRight => "while tape[ptr] != 0 { ptr += 1; }".to_string(),
+            ScanLeft => "while tape[ptr] != 0 { ptr -= 1; }".to_string(),
+            Halt => "stdout.flush().unwrap(); return;".to_string(),
+            PrintInt => "write!(stdout, \"{}\", tape[ptr]).unwrap();".to_string(),
+            Random => format!(
+                concat!(
+                    "{{ rng = rng.wrapping_add(0x9e3779b97f4a7c15); ",
+                    "let z = (rng ^ (rng >> 30)).wrapping_mul(0xbf58476d1ce4e5b9); ",
+                    "let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb); ",
+                    "tape[ptr] = ((z ^ (z >> 31)) >> 56) as {}; }}"
+                ),
+                self.cell_type()
+            ),
+            Reset => format!(
+                "tape.iter_mut().for_each(|c| *c = 0); ptr = {};",
+                self.config.start_offset
+            ),
+            ClearRange { from, len } => format!(
+                "tape[{}..{}].iter_mut().for_each(|c| *c = 0);",
+                index(from),
+                index(from + len as isize)
+            ),
+            MulAdd { offset, factor: 1 } => {
+                format!("tape[{i}] = tape[{i}].wrapping_add(tape[ptr]);", i = index(offset))
+            }
+            MulAdd { offset, factor } => {
+                format!(
+                    "tape[{i}] = tape[{i}].wrapping_add(tape[ptr].wrapping_mul({}));",
+                    factor as u32 & self.config.cell_size.mask(),
+                    i = index(offset)
+                )
+            }
+            AddAt { offset, val } => format!(
+                "tape[{i}] = tape[{i}].wrapping_add({});",
+                val as u32 & self.config.cell_size.mask(),
+                i = index(offset)
+            ),
+            Dot => "stdout.write_all(&[tape[ptr] as u8]).unwrap();".to_string(),
+            WriteRepeat { count } => {
+                format!("stdout.write_all(&[tape[ptr] as u8; {}]).unwrap();", count)
+            }
+            Comma => format!(
+                "if stdin.read(&mut buf).unwrap() == 1 {{ tape[ptr] = buf[0] as {}; }} else {{ {}}}",
+                self.cell_type(),
+                eof
+            ),
+            LBracket(_) | LoopStart(_) | RBracket(_) | Comment(_) => unreachable!(),
+        }
+    }
+
+    fn loop_start(&self, guarded: bool) -> String {
+        match guarded {
+            true => "while tape[ptr] != 0 {".to_string(),
+            false => "loop {".to_string(),
+        }
+    }
+
+    fn loop_end(&self, guarded: bool) -> String {
+        match guarded {
+            true => "}".to_string(),
+            false => "if tape[ptr] == 0 { break; } }".to_string(),
+        }
+    }
+
+    fn position(&self, line: usize, col: usize) -> String {
+        format!("// line {}, column {}", line, col)
+    }
+}
+
+/// Emits a WebAssembly module whose linear memory holds the tape, with the
+/// pointer kept as a byte address in the `$ptr` local
+struct WatBackend<'a> {
+    config: &'a Config,
+    /// Whether to import `print_int` for `PrintInt`
+    print_int: bool,
+    /// Whether to declare the generator state for `Random`
+    random: bool,
+}
+
+impl WatBackend<'_> {
+    /// Width of a cell in bytes
+    fn width(&self) -> isize {
+        match self.config.cell_size {
+            CellSize::U8 => 1,
+            CellSize::U16 => 2,
+            CellSize::U32 => 4,
+        }
+    }
+
+    /// Address of the cell at `offset` from the pointer
+    fn addr(&self, offset: isize) -> String {
+        match offset * self.width() {
+            0 => "(local.get $ptr)".to_string(),
+            n => format!("(i32.add (local.get $ptr) (i32.const {}))", n),
+        }
+    }
+
+    fn load(&self, offset: isize) -> String {
+        let op = match self.config.cell_size {
+            CellSize::U8 => "i32.load8_u",
+            CellSize::U16 => "i32.load16_u",
+            CellSize::U32 => "i32.load",
+        };
+        format!("({} {})", op, self.addr(offset))
+    }
+
+    /// Stores `value`, which is truncated to the cell width
+    fn store(&self, offset: isize, value: &str) -> String {
+        let op = match self.config.cell_size {
+            CellSize::U8 => "i32.store8",
+            CellSize::U16 => "i32.store16",
+            CellSize::U32 => "i32.store",
+        };
+        format!("({} {} {})", op, self.addr(offset), value)
+    }
+
+    fn add(&self, offset: isize, value: &str) -> String {
+        let sum = format!("(i32.add {} {})", self.load(offset), value);
+        self.store(offset, &sum)
+    }
+
+    fn move_by(&self, n: isize) -> String {
+        format!(
+            "(local.set $ptr (i32.add (local.get $ptr) (i32.const {})))",
+            n * self.width()
+        )
+    }
+
+    fn scan(&self, n: isize) -> String {
+        format!(
+            "(block (loop (br_if 1 (i32.eqz {})) {} (br 0)))",
+            self.load(0),
+            self.move_by(n)
+        )
+    }
+}
+
+impl Backend for WatBackend<'_> {
+    fn header(&self, out: &mut String) {
+        let size = self.config.tape_size.unwrap_or(DEFAULT_TAPE_SIZE) as isize * self.width();
+        let pages = ((size + 0xffff) / 0x10000).max(1);
+        writeln!(out, "(module").unwrap();
+        writeln!(
+            out,
+            "  (import \"env\" \"putchar\" (func $putchar (param i32)))"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "  (import \"env\" \"getchar\" (func $getchar (result i32)))"
+        )
+        .unwrap();
+        if self.print_int {
+            writeln!(
+                out,
+                "  (import \"env\" \"print_int\" (func $print_int (param i32)))"
+            )
+            .unwrap();
+        }
+        writeln!(out, "  (memory (export \"memory\") {})", pages).unwrap();
+        if self.random {
+            let seed = self.config.seed;
+            writeln!(out, "  (global $rng (mut i64) (i64.const {}))", seed).unwrap();
+        }
+        writeln!(out, "  (func (export \"run\")").unwrap();
+        writeln!(out, "    (local $ptr i32)").unwrap();
+        writeln!(out, "    (local $c i32)").unwrap();
+        if self.random {
+            writeln!(out, "    (local $z i64)").unwrap();
+        }
+        writeln!(
+            out,
+            "    (local.set $ptr (i32.const {}))",
+            self.config.start_offset as isize * self.width()
+        )
+        .unwrap();
+    }
+
+    fn footer(&self, out: &mut String) {
+        writeln!(out, "  )").unwrap();
+        writeln!(out, ")").unwrap();
+    }
+
+    fn op(&self, op: BfOp) -> String {
+        let c = "(local.get $c)";
+        let got = "(i32.ge_s (local.get $c) (i32.const 0))";
+        match op {
+            Gt => self.move_by(1),
+            Lt => self.move_by(-1),
+            Plus => self.add(0, "(i32.const 1)"),
+            Minus => self.add(0, "(i32.const -1)"),
+            Add(n) => self.add(0, &format!("(i32.const {})", n)),
+            Move(n) => self.move_by(n),
+            Clear => self.store(0, "(i32.const 0)"),
+            SetValue(n) => {
+                let n = n & self.config.cell_size.mask();
+                self.store(0, &format!("(i32.const {})", n as i32))
+            }
+            Debug => ";; #".to_string(),
+            ScanRight => self.scan(1),
+            ScanLeft => self.scan(-1),
+            Halt => "(return)".to_string(),
+            PrintInt => format!("(call $print_int {})", self.load(0)),
+            // The interpreter's splitmix64 generator
+            Random => {
+                let mix = |shift, factor| {
+                    format!(
+                        "(local.set $z (i64.mul (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const {}))) (i64.const {})))",
+                        shift, factor
+                    )
+                };
+                let byte = "(i32.wrap_i64 (i64.shr_u (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 31))) (i64.const 56)))";
+                format!(
+                    "(global.set $rng (i64.add (global.get $rng) (i64.const 0x9e3779b97f4a7c15))) (local.set $z (global.get $rng)) {} {} {}",
+                    mix(30, "0xbf58476d1ce4e5b9"),
+                    mix(27, "0x94d049bb133111eb"),
+                    self.store(0, byte)
+                )
+            }
+            Reset => format!(
+                "(memory.fill (i32.const 0) (i32.const 0) (i32.const {})) (local.set $ptr (i32.const {}))",
+                self.config.tape_size.unwrap_or(DEFAULT_TAPE_SIZE) as isize * self.width(),
+                self.config.start_offset as isize * self.width()
+            ),
+            ClearRange { from, len } => format!(
+                "(memory.fill {} (i32.const 0) (i32.const {}))",
+                self.addr(from),
+                len as isize * self.width()
+            ),
+            MulAdd { offset, factor: 1 } => self.add(offset, &self.load(0)),
+            MulAdd { offset, factor } => self.add(
+                offset,
+                &format!("(i32.mul {} (i32.const {}))", self.load(0), factor),
+            ),
+            AddAt { offset, val } => self.add(offset, &format!("(i32.const {})", val)),
+            Dot => format!("(call $putchar {})", self.load(0)),
+            WriteRepeat { count } => {
+                let call = format!("(call $putchar {})", self.load(0));
+                vec![call; count].join(" ")
+            }
+            // getchar returns a negative value at end of input
+            Comma => {
+                let read = "(local.set $c (call $getchar))";
+                match self.config.eof {
+                    Eof::Zero => format!(
+                        "{} {}",
+                        read,
+                        self.store(0, &format!("(select {} (i32.const 0) {})", c, got))
+                    ),
+                    Eof::MinusOne => format!(
+                        "{} {}",
+                        read,
+                        self.store(0, &format!("(select {} (i32.const -1) {})", c, got))
+                    ),
+                    Eof::Unchanged => {
+                        format!("{} (if {} (then {}))", read, got, self.store(0, c))
+                    }
+                }
+            }
+            LBracket(_) | LoopStart(_) | RBracket(_) | Comment(_) => unreachable!(),
+        }
+    }
+
+    fn loop_start(&self, guarded: bool) -> String {
+        match guarded {
+            true => format!("(block (loop (br_if 1 (i32.eqz {}))", self.load(0)),
+            false => "(block (loop".to_string(),
+        }
+    }
+
+    fn loop_end(&self, _: bool) -> String {
+        format!("(br_if 0 {})))", self.load(0))
+    }
+
+    fn position(&self, line: usize, col: usize) -> String {
+        format!(";; line {}, column {}", line, col)
+    }
+}
+
+/// Whether a program uses `Random`, and so needs the generator state
+fn uses_random(instrs: &[BfOp]) -> bool {
+    instrs.iter().any(|op| matches!(op, Random))
+}
+
+/// Translates a program into equivalent C source
+pub fn emit_c(instrs: &[BfOp], config: &Config) -> String {
+    emit_with(&CBackend::new(instrs, config), instrs, None)
+}
+
+/// Like `emit_c`, with a comment before each statement giving the line and
+/// column in `code` it came from, where `offsets` holds the source offset of
+/// each instruction, as from `parse_offsets` or `Interpreter::source_offset`
+///
+/// ```
+/// use brainfuck::{emit, parse_offsets, Config, ParseOptions};
+///
+/// let code = b"+\n[-]";
+/// let (instrs, offsets) = parse_offsets(code, ParseOptions::default()).unwrap();
+/// let c = emit::emit_c_debug(&instrs, &Config::default(), code, &offsets);
+/// assert!(c.contains("/* line 1, column 1 */") && c.contains("/* line 2, column 2 */"));
+/// assert!(!emit::emit_c(&instrs, &Config::default()).contains("/* line"));
+/// ```
+pub fn emit_c_debug(instrs: &[BfOp], config: &Config, code: &[u8], offsets: &[usize]) -> String {
+    let positions = positions(code, offsets);
+    emit_with(&CBackend::new(instrs, config), instrs, Some(&positions))
+}
+
+/// Translates a program into an equivalent standalone Rust program
+pub fn emit_rust(instrs: &[BfOp], config: &Config) -> String {
+    emit_with(&RustBackend::new(instrs, config), instrs, None)
+}
+
+/// Like `emit_rust`, with a comment before each statement giving the line
+/// and column in `code` it came from, as for `emit_c_debug`
+pub fn emit_rust_debug(instrs: &[BfOp], config: &Config, code: &[u8], offsets: &[usize]) -> String {
+    let positions = positions(code, offsets);
+    emit_with(&RustBackend::new(instrs, config), instrs, Some(&positions))
+}
+
+/// The line and column of each source offset
+fn positions(code: &[u8], offsets: &[usize]) -> Vec<(usize, usize)> {
+    offsets
+        .iter()
+        .map(|&offset| line_col(code, offset.min(code.len())))
+        .collect()
+}
+
+/// Translates a program into a WebAssembly text module exporting a `run`
+/// function and the `memory` holding the tape
+///
+/// The module imports `putchar` and `getchar` from `env`, with `getchar`
+/// returning a negative value at end of input, and `print_int` if the
+/// program uses it.
+///
+/// ```
+/// use brainfuck::{emit, parse, Config};
+///
+/// let wat = emit::emit_wat(&parse(b"+[-.]").unwrap(), &Config::default());
+/// assert!(wat.contains("(loop") && wat.contains("br_if"));
+/// assert_eq!(wat.matches('(').count(), wat.matches(')').count());
+/// ```
+pub fn emit_wat(instrs: &[BfOp], config: &Config) -> String {
+    let backend = WatBackend {
+        config,
+        print_int: instrs.iter().any(|op| matches!(op, PrintInt)),
+        random: uses_random(instrs),
+    };
+    emit_with(&backend, instrs, None)
+}
+
+/// Draws the control-flow graph of a program in Graphviz DOT format
+///
+/// Each straight-line run of instructions between brackets is a node, and
+/// each bracket adds an edge taken when the current cell is zero and one
+/// taken when it isn't.
+pub fn emit_dot(instrs: &[BfOp]) -> String {
+    let brackets: Vec<usize> = (0..instrs.len())
+        .filter(|&i| matches!(instrs[i], LBracket(_) | LoopStart(_) | RBracket(_)))
+        .collect();
+    // Block k runs from just after bracket k - 1 up to bracket k
+    let block_of = |bracket: usize| brackets.binary_search(&bracket).unwrap();
+    let mut out = String::new();
+    writeln!(out, "digraph program {{").unwrap();
+    writeln!(out, "    node [shape=box];").unwrap();
+    writeln!(out, "    start [shape=oval];").unwrap();
+    writeln!(out, "    end [shape=oval];").unwrap();
+    for k in 0..=brackets.len() {
+        let start = if k == 0 { 0 } else { brackets[k - 1] + 1 };
+        let end = brackets.get(k).copied().unwrap_or(instrs.len());
+        let mut ops: Vec<_> = instrs[start..end]
+            .iter()
+            .take(8)
+            .map(|op| format!("{:?}", op))
+            .collect();
+        if end - start > 8 {
+            ops.push("...".to_string());
+        }
+        let summary = if ops.is_empty() {
+            "(empty)".to_string()
+        } else {
+            ops.join(" ")
+        };
+        writeln!(
+            out,
+            "    b{} [label=\"{}..{}\\n{}\"];",
+            k, start, end, summary
+        )
+        .unwrap();
+    }
+    writeln!(out, "    start -> b0;").unwrap();
+    writeln!(out, "    b{} -> end;", brackets.len()).unwrap();
+    for (k, &i) in brackets.iter().enumerate() {
+        let (nonzero, zero) = match instrs[i] {
+            LBracket(j) => (k + 1, block_of(j) + 1),
+            // Entered without a test, so both edges go into the body
+            LoopStart(_) => (k + 1, k + 1),
+            RBracket(j) => (block_of(j) + 1, k + 1),
+            _ => unreachable!(),
+        };
+        writeln!(out, "    b{} -> b{} [label=\"nonzero\"];", k, nonzero).unwrap();
+        writeln!(out, "    b{} -> b{} [label=\"zero\"];", k, zero).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_with, ParseOptions};
+
+    fn extended(code: &str) -> Vec<BfOp> {
+        let opts = ParseOptions {
+            extensions: true,
+            ..ParseOptions::default()
+        };
+        parse_with(code.as_bytes(), opts).unwrap()
+    }
+
+    #[test]
+    fn seed_is_only_emitted_for_random() {
+        let configs = [1, 2].map(|seed| Config {
+            seed,
+            ..Config::default()
+        });
+        let plain = extended("+.");
+        for emit in [emit_c, emit_rust] {
+            assert_eq!(emit(&plain, &configs[0]), emit(&plain, &configs[1]));
+            assert!(!emit(&plain, &configs[0]).contains("rng"));
+        }
+        let random = extended("?.");
+        assert!(emit_c(&random, &configs[1]).contains("uint64_t rng = 2ULL, z;"));
+        assert!(emit_rust(&random, &configs[1]).contains("let mut rng = 2u64;"));
+    }
+
+    #[test]
+    fn c_for_plus_dot() {
+        let config = Config {
+            tape_size: Some(10),
+            ..Config::default()
+        };
+        let c = emit_c(&crate::parse(b"+.").unwrap(), &config);
+        let expected = [
+            "#include <stdint.h>",
+            "#include <stdio.h>",
+            "#include <string.h>",
+            "",
+            "uint8_t tape[10];",
+            "",
+            "int main(void) {",
+            "    size_t ptr = 0;",
+            "    int c;",
+            "    tape[ptr]++;",
+            "    putchar(tape[ptr]);",
+            "    return 0;",
+            "}",
+        ];
+        assert_eq!(c.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn rust_has_a_while_loop_per_bracket_pair() {
+        let rust = emit_rust(&crate::parse(b"+[>+[-]<-]").unwrap(), &Config::default());
+        assert_eq!(rust.matches("while tape[ptr] != 0 {").count(), 2);
+        assert!(rust.contains("        while tape[ptr] != 0 {"));
+        assert!(rust.contains("tape[ptr] = tape[ptr].wrapping_sub(1);"));
+        assert_eq!(rust.matches('{').count(), rust.matches('}').count());
+    }
+
+    #[test]
+    fn dot_has_a_back_edge_per_loop() {
+        let dot = emit_dot(&extended("+[>+[-]<-]"));
+        let back_edges: Vec<_> = dot
+            .lines()
+            .filter(|line| line.ends_with("[label=\"nonzero\"];"))
+            .filter(|line| {
+                let blocks: Vec<usize> = line
+                    .split_whitespace()
+                    .take(3)
+                    .filter_map(|node| node.strip_prefix('b')?.parse().ok())
+                    .collect();
+                blocks[1] <= blocks[0]
+            })
+            .map(str::trim)
+            .collect();
+        assert_eq!(
+            back_edges,
+            [
+                "b2 -> b2 [label=\"nonzero\"];",
+                "b3 -> b1 [label=\"nonzero\"];"
+            ]
+        );
+    }
+}
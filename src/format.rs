@@ -0,0 +1,294 @@
+//! Source formatting over the token stream
+
+use crate::BfOp::{self, *};
+use std::collections::BTreeMap;
+
+/// Appends the source of an unoptimized instruction
+fn push_token(out: &mut String, op: &BfOp) {
+    let c = match *op {
+        Gt => '>',
+        Lt => '<',
+        Plus => '+',
+        Minus => '-',
+        Dot => '.',
+        Comma => ',',
+        LBracket(_) => '[',
+        RBracket(_) => ']',
+        Debug => '#',
+        Halt => '@',
+        PrintInt => '&',
+        Random => '?',
+        Reset => '~',
+        SetValue(n) => {
+            out.push_str(&format!("{{{}}}", n));
+            return;
+        }
+        _ => panic!("{:?} is not a source token", op),
+    };
+    out.push(c);
+}
+
+/// Writes the program on one line with comments and whitespace removed
+pub fn minify(tokens: &[BfOp]) -> String {
+    let mut out = String::new();
+    for op in tokens.iter().filter(|op| !matches!(op, Comment(_))) {
+        push_token(&mut out, op);
+    }
+    out.push('\n');
+    out
+}
+
+/// Writes the program with each bracket on its own line and loop bodies
+/// indented by four spaces per level of nesting, putting the lines of any
+/// comments on lines of their own
+pub fn indent(tokens: &[BfOp]) -> String {
+    fn flush(line: &mut String, out: &mut String, depth: usize) {
+        if !line.is_empty() {
+            out.push_str(&" ".repeat(depth * 4));
+            out.push_str(line);
+            out.push('\n');
+            line.clear();
+        }
+    }
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut depth = 0;
+    for op in tokens {
+        match op {
+            LBracket(_) => {
+                flush(&mut line, &mut out, depth);
+                out.push_str(&" ".repeat(depth * 4));
+                out.push_str("[\n");
+                depth += 1;
+            }
+            RBracket(_) => {
+                flush(&mut line, &mut out, depth);
+                depth -= 1;
+                out.push_str(&" ".repeat(depth * 4));
+                out.push_str("]\n");
+            }
+            Comment(text) => {
+                flush(&mut line, &mut out, depth);
+                for text in String::from_utf8_lossy(text).lines() {
+                    line.push_str(text.trim());
+                    flush(&mut line, &mut out, depth);
+                }
+            }
+            op => push_token(&mut line, op),
+        }
+    }
+    flush(&mut line, &mut out, depth);
+    out
+}
+
+/// Appends `n` repetitions of `pos` if `n` is positive, or of `neg` if not
+fn repeat(out: &mut String, n: i64, pos: char, neg: char) {
+    let c = if n < 0 { neg } else { pos };
+    out.extend(std::iter::repeat_n(c, n.unsigned_abs() as usize));
+}
+
+/// Reconstructs brainfuck source from instructions, expanding optimized
+/// instructions into equivalent commands
+///
+/// `#`, `@`, `?`, `{n}` and `&` (for `PrintInt`, whatever character it was parsed from)
+/// are only recognized again if the matching parse options are enabled.
+/// Comments are written back as they were, lossily if they aren't UTF-8, so
+/// tokens kept with `keep_comments` reproduce their source exactly.
+///
+/// Panics on a run of `MulAdd`s that isn't followed by `Clear`, as the
+/// optimizer always emits them, since they can't be expressed alone.
+///
+/// ```
+/// use brainfuck::{format, tokenize_with, ParseOptions};
+///
+/// let source = "read a byte: ,\n[echo it .,]\n";
+/// let opts = ParseOptions {
+///     keep_comments: true,
+///     ..ParseOptions::default()
+/// };
+/// let tokens = tokenize_with(source.as_bytes(), opts);
+/// assert_eq!(format::to_source(&tokens), source);
+/// ```
+pub fn to_source(instrs: &[BfOp]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < instrs.len() {
+        match instrs[i] {
+            Comment(ref text) => out.push_str(&String::from_utf8_lossy(text)),
+            Add(n) => repeat(&mut out, n as i64, '+', '-'),
+            Move(n) => repeat(&mut out, n as i64, '>', '<'),
+            Clear => out.push_str("[-]"),
+            ScanRight => out.push_str("[>]"),
+            ScanLeft => out.push_str("[<]"),
+            AddAt { offset, val } => {
+                repeat(&mut out, offset as i64, '>', '<');
+                repeat(&mut out, val as i64, '+', '-');
+                repeat(&mut out, -(offset as i64), '>', '<');
+            }
+            LoopStart(_) => out.push('['),
+            WriteRepeat { count } => out.push_str(&".".repeat(count)),
+            ClearRange { from, len } => {
+                repeat(&mut out, from as i64, '>', '<');
+                for k in 0..len {
+                    if k > 0 {
+                        out.push('>');
+                    }
+                    out.push_str("[-]");
+                }
+                repeat(&mut out, -(from + len as isize - 1) as i64, '>', '<');
+            }
+            MulAdd { .. } => {
+                // A multiplication loop, which adds to each target once per
+                // decrement of the current cell
+                out.push_str("[-");
+                while let Some(&MulAdd { offset, factor }) = instrs.get(i) {
+                    repeat(&mut out, offset as i64, '>', '<');
+                    repeat(&mut out, factor as i64, '+', '-');
+                    repeat(&mut out, -(offset as i64), '>', '<');
+                    i += 1;
+                }
+                assert!(
+                    matches!(instrs.get(i), Some(Clear)),
+                    "MulAdd must be followed by Clear"
+                );
+                out.push(']');
+            }
+            ref op => push_token(&mut out, op),
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Summarizes a program with its instruction counts by kind and its first
+/// and last `n` instructions, or lists every instruction if there are at most
+/// `2 * n`
+pub fn summary(instrs: &[BfOp], n: usize) -> String {
+    if instrs.len() <= 2 * n {
+        return format!("{} instructions: {:?}\n", instrs.len(), instrs);
+    }
+    let mut counts = BTreeMap::new();
+    for op in instrs {
+        *counts.entry(op.name()).or_insert(0) += 1;
+    }
+    let mut out = format!("{} instructions\n", instrs.len());
+    for (name, count) in counts {
+        out.push_str(&format!("  {:<10} {}\n", name, count));
+    }
+    out.push_str(&format!("First {}: {:?}\n", n, &instrs[..n]));
+    out.push_str(&format!("Last {}: {:?}\n", n, &instrs[instrs.len() - n..]));
+    out
+}
+
+/// Lists instructions one per line with their index, name and any operands,
+/// giving the index of the matching bracket for brackets
+///
+/// ```
+/// use brainfuck::{format, optimize, parse, OptLevel};
+///
+/// let instrs = optimize(parse(b"++++[-]").unwrap(), OptLevel::O1);
+/// assert_eq!(format::listing(&instrs), "0  Add 4\n1  Clear\n");
+/// ```
+pub fn listing(instrs: &[BfOp]) -> String {
+    let width = instrs.len().saturating_sub(1).to_string().len();
+    let mut out = String::new();
+    for (i, op) in instrs.iter().enumerate() {
+        let operands = match *op {
+            Add(n) => n.to_string(),
+            Move(n) => n.to_string(),
+            LBracket(j) | LoopStart(j) | RBracket(j) => format!("-> {}", j),
+            MulAdd { offset, factor } => format!("offset {}, factor {}", offset, factor),
+            AddAt { offset, val } => format!("offset {}, val {}", offset, val),
+            ClearRange { from, len } => format!("from {}, len {}", from, len),
+            SetValue(n) => n.to_string(),
+            WriteRepeat { count } => format!("count {}", count),
+            Comment(ref text) => format!("{} bytes", text.len()),
+            _ => String::new(),
+        };
+        let line = format!("{:>w$}  {} {}", i, op.name(), operands, w = width);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::OptLevel;
+    use crate::{optimize, parse, tokenize_with, Config, Interpreter, ParseOptions};
+    use std::io;
+
+    fn commented(code: &str) -> Vec<BfOp> {
+        let opts = ParseOptions {
+            keep_comments: true,
+            ..ParseOptions::default()
+        };
+        tokenize_with(code.as_bytes(), opts)
+    }
+
+    #[test]
+    fn minify_drops_comments() {
+        assert_eq!(minify(&commented("add two ++\nprint it .")), "++.\n");
+    }
+
+    #[test]
+    fn indent_follows_the_nesting() {
+        let out = indent(&commented("+[>[-]<-]"));
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "+",
+                "[",
+                "    >",
+                "    [",
+                "        -",
+                "    ]",
+                "    <-",
+                "]"
+            ]
+        );
+    }
+
+    #[test]
+    fn to_source_runs_like_the_optimized_program() {
+        let samples = [
+            "+++++[->++>+++<<]>.>.",
+            "+>++>+++<<.>.>.",
+            ">+++>+<[<]>[-]+.",
+            ">>+<+<+[-]>[-]>[-]<<+.",
+        ];
+        let mut seen = Vec::new();
+        for code in &samples {
+            let instrs = optimize(parse(code.as_bytes()).unwrap(), OptLevel::O2);
+            seen.extend(instrs.iter().map(|op| format!("{:?}", op)));
+            let source = to_source(&instrs);
+
+            let mut optimized = Interpreter::new(Config::default());
+            optimized.load_ops(instrs);
+            let mut expected = Vec::new();
+            optimized.run(io::empty(), &mut expected).unwrap();
+            let mut rebuilt = Interpreter::new(Config {
+                opt_level: OptLevel::O0,
+                ..Config::default()
+            });
+            let mut out = Vec::new();
+            rebuilt.eval(&source, io::empty(), &mut out).unwrap();
+            assert_eq!(out, expected, "{}", source);
+            assert_eq!(rebuilt.tape(), optimized.tape(), "{}", source);
+        }
+        for name in &["MulAdd", "ClearRange", "AddAt"] {
+            assert!(seen.iter().any(|op| op.starts_with(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn summary_of_a_long_program_is_short() {
+        let instrs = parse(&b"+>".repeat(500)).unwrap();
+        let out = summary(&instrs, 5);
+        assert!(out.starts_with("1000 instructions\n"));
+        assert!(out.lines().count() < 10);
+        assert!(out.contains("First 5: [Plus, Gt, Plus, Gt, Plus]"));
+    }
+}
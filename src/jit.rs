@@ -0,0 +1,557 @@
+//! A JIT compiler from instructions to x86-64 machine code
+//!
+//! The generated function keeps the tape base in `rbx`, the pointer as an
+//! index in `r12`, the context in `r13`, the tape length in `r14` and the
+//! highest cell index used in `r15`, and calls back into Rust for I/O and
+//! `#`. Every pointer move is bounds-checked against the tape, which has a
+//! fixed size since it can't grow while the machine code runs.
+
+use crate::emit::DEFAULT_TAPE_SIZE;
+use crate::tape::Tape;
+use crate::BfOp::{self, *};
+use crate::{debug_window, random_byte, read_byte, BfError, CellSize, Config, Eof, Interpreter};
+use crate::{IDENTITY_MAP, REPEAT_CHUNK};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::ptr;
+
+/// State shared with the generated code, which reads and writes the first
+/// four fields directly
+#[repr(C)]
+struct Context<'a> {
+    /// The pointer on entry and exit
+    ptr: usize,
+    /// The instruction and cell position of a pointer out of bounds
+    fault_pc: usize,
+    fault_position: isize,
+    /// The highest cell index used, on exit and during `debug`
+    max_index: usize,
+    tape: *const u8,
+    len: usize,
+    /// The length of the interpreter's tape before running
+    initial_len: usize,
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    eof: Eof,
+    sticky_eof: bool,
+    /// Whether a read has reached EOF, with `sticky_eof`
+    at_eof: bool,
+    unbuffered: bool,
+    rng: u64,
+    error: Option<io::Error>,
+}
+
+/// Exit statuses of the generated code
+const OK: u32 = 0;
+const IO_ERROR: u32 = 1;
+const OUT_OF_BOUNDS: u32 = 2;
+
+type JitFn = unsafe extern "sysv64" fn(tape: *mut u8, len: usize, ctx: *mut Context) -> u32;
+
+extern "sysv64" fn write_cell(ctx: &mut Context, value: u32) -> i32 {
+    write_bytes(ctx, &[value as u8])
+}
+
+extern "sysv64" fn write_repeat(ctx: &mut Context, value: u32, count: u32) -> i32 {
+    let chunk = [value as u8; REPEAT_CHUNK];
+    let mut left = count as usize;
+    while left > 0 {
+        let n = left.min(REPEAT_CHUNK);
+        if write_bytes(ctx, &chunk[..n]) != 0 {
+            return -1;
+        }
+        left -= n;
+    }
+    0
+}
+
+extern "sysv64" fn print_int(ctx: &mut Context, value: u32) -> i32 {
+    write_bytes(ctx, value.to_string().as_bytes())
+}
+
+fn write_bytes(ctx: &mut Context, bytes: &[u8]) -> i32 {
+    let mut result = ctx.output.write_all(bytes);
+    if ctx.unbuffered {
+        result = result.and_then(|_| ctx.output.flush());
+    }
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            ctx.error = Some(e);
+            -1
+        }
+    }
+}
+
+extern "sysv64" fn read_cell(ctx: &mut Context, value: u32) -> i32 {
+    if let Err(e) = ctx.output.flush() {
+        ctx.error = Some(e);
+        return -1;
+    }
+    let read = match ctx.at_eof {
+        true => Ok(None),
+        false => read_byte(&mut ctx.input),
+    };
+    ctx.at_eof = ctx.sticky_eof && matches!(read, Ok(None));
+    match read {
+        Ok(Some(byte)) => byte as i32,
+        Ok(None) => match ctx.eof {
+            Eof::Zero => 0,
+            Eof::MinusOne => 0xff,
+            Eof::Unchanged => value as i32,
+        },
+        Err(e) => {
+            ctx.error = Some(e);
+            -1
+        }
+    }
+}
+
+extern "sysv64" fn random_cell(ctx: &mut Context) -> i32 {
+    random_byte(&mut ctx.rng) as i32
+}
+
+extern "sysv64" fn debug(ctx: &mut Context, ptr: usize) {
+    // Safety: the tape outlives the generated code's execution
+    let tape = unsafe { std::slice::from_raw_parts(ctx.tape, ctx.len) };
+    let used = used_len(ctx.max_index, ctx.initial_len);
+    eprintln!("{}", debug_window(used, ptr, |i| tape[i]));
+}
+
+/// The length the interpreter's tape would have grown to: at least `min`
+/// cells, and enough to hold every cell used
+fn used_len(max_index: usize, min: usize) -> usize {
+    min.max(max_index + 1)
+}
+
+/// Machine code under construction, with the locations of jumps to the
+/// shared exits
+struct Assembler {
+    code: Vec<u8>,
+    halts: Vec<usize>,
+    errors: Vec<usize>,
+    faults: Vec<usize>,
+}
+
+impl Assembler {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, n: i32) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    /// Emits a jump opcode with a placeholder target, returning its location
+    fn jump(&mut self, opcode: &[u8]) -> usize {
+        self.bytes(opcode);
+        self.imm32(0);
+        self.code.len() - 4
+    }
+
+    /// Points the jump at `at` to `target`
+    fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i32 - (at + 4) as i32;
+        self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    /// Calls `f` with the context and the given second argument already in
+    /// `rsi`
+    fn call(&mut self, f: *const ()) {
+        self.bytes(&[0x4c, 0x89, 0xef]); // mov rdi, r13
+        self.bytes(&[0x48, 0xb8]); // mov rax, f
+        self.bytes(&(f as u64).to_le_bytes());
+        self.bytes(&[0xff, 0xd0]); // call rax
+    }
+
+    /// Exits with an error if the I/O callback just called failed
+    fn check_io(&mut self) {
+        self.bytes(&[0x85, 0xc0]); // test eax, eax
+        let at = self.jump(&[0x0f, 0x88]); // js error
+        self.errors.push(at);
+    }
+
+    /// Exits with an error for instruction `pc` unless `rax` is a cell index
+    fn check_bounds(&mut self, pc: usize) {
+        self.bytes(&[0x4c, 0x39, 0xf0]); // cmp rax, r14
+        self.bytes(&[0x72, 0x0a]); // jb past the fault
+        self.bytes(&[0xb9]); // mov ecx, pc
+        self.imm32(pc as i32);
+        let at = self.jump(&[0xe9]); // jmp fault
+        self.faults.push(at);
+        self.bytes(&[0x49, 0x39, 0xc7]); // cmp r15, rax
+        self.bytes(&[0x4c, 0x0f, 0x42, 0xf8]); // cmovb r15, rax
+    }
+
+    /// Moves the pointer by `n` cells
+    fn move_by(&mut self, n: i32, pc: usize) {
+        self.bytes(&[0x49, 0x81, 0xc4]); // add r12, n
+        self.imm32(n);
+        self.bytes(&[0x4c, 0x89, 0xe0]); // mov rax, r12
+        self.check_bounds(pc);
+    }
+
+    /// Adds `n` to the current cell
+    fn add(&mut self, n: i32) {
+        self.bytes(&[0x42, 0x80, 0x04, 0x23, n as u8]); // add byte [rbx + r12], n
+    }
+
+    /// Loads the index of the cell at offset `n` into `rax`
+    fn index(&mut self, n: i32, pc: usize) {
+        self.bytes(&[0x49, 0x8d, 0x84, 0x24]); // lea rax, [r12 + n]
+        self.imm32(n);
+        self.check_bounds(pc);
+    }
+
+    /// Compares the current cell with zero
+    fn test_cell(&mut self) {
+        self.bytes(&[0x42, 0x80, 0x3c, 0x23, 0x00]); // cmp byte [rbx + r12], 0
+    }
+}
+
+/// Converts an operand to an immediate, which must fit in 32 bits
+fn imm(n: isize) -> Result<i32, BfError> {
+    n.try_into()
+        .map_err(|_| BfError::ConfigError("offset too large for the JIT"))
+}
+
+/// Compiles instructions with matched brackets to a function body
+fn compile(instrs: &[BfOp], config: &Config) -> Result<Vec<u8>, BfError> {
+    let mut asm = Assembler {
+        code: Vec::new(),
+        halts: Vec::new(),
+        errors: Vec::new(),
+        faults: Vec::new(),
+    };
+    asm.bytes(&[0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]); // push rbx, r12-r15
+    asm.bytes(&[0x48, 0x89, 0xfb]); // mov rbx, rdi
+    asm.bytes(&[0x49, 0x89, 0xf6]); // mov r14, rsi
+    asm.bytes(&[0x49, 0x89, 0xd5]); // mov r13, rdx
+    asm.bytes(&[0x4d, 0x8b, 0x65, 0x00]); // mov r12, [r13]
+    asm.bytes(&[0x4d, 0x89, 0xe7]); // mov r15, r12
+
+    // Where the body of each open loop starts, and the location of its
+    // jump past the loop if it tests the cell on entry
+    let mut loops = Vec::new();
+    for (pc, op) in instrs.iter().enumerate() {
+        match *op {
+            Gt => asm.move_by(1, pc),
+            Lt => asm.move_by(-1, pc),
+            Move(n) => asm.move_by(imm(n)?, pc),
+            Plus => asm.add(1),
+            Minus => asm.add(-1),
+            Add(n) => asm.add(n),
+            Clear => asm.bytes(&[0x42, 0xc6, 0x04, 0x23, 0x00]), // mov byte [rbx + r12], 0
+            SetValue(n) => asm.bytes(&[0x42, 0xc6, 0x04, 0x23, n as u8]), // mov byte [rbx + r12], n
+            AddAt { offset, val } => {
+                asm.index(imm(offset)?, pc);
+                asm.bytes(&[0x80, 0x04, 0x03, val as u8]); // add byte [rbx + rax], val
+            }
+            MulAdd { offset, factor } => {
+                asm.bytes(&[0x42, 0x0f, 0xb6, 0x0c, 0x23]); // movzx ecx, byte [rbx + r12]
+                asm.bytes(&[0x85, 0xc9]); // test ecx, ecx
+                let skip = asm.jump(&[0x0f, 0x84]); // jz past the add
+                asm.index(imm(offset)?, pc);
+                if factor != 1 {
+                    asm.bytes(&[0x69, 0xc9]); // imul ecx, ecx, factor
+                    asm.imm32(factor);
+                }
+                asm.bytes(&[0x00, 0x0c, 0x03]); // add byte [rbx + rax], cl
+                let end = asm.code.len();
+                asm.patch(skip, end);
+            }
+            ScanRight | ScanLeft => {
+                let top = asm.code.len();
+                asm.test_cell();
+                let done = asm.jump(&[0x0f, 0x84]); // je done
+                asm.move_by(if let ScanRight = op { 1 } else { -1 }, pc);
+                let back = asm.jump(&[0xe9]); // jmp top
+                asm.patch(back, top);
+                let end = asm.code.len();
+                asm.patch(done, end);
+            }
+            Dot => {
+                asm.bytes(&[0x42, 0x0f, 0xb6, 0x34, 0x23]); // movzx esi, byte [rbx + r12]
+                asm.call(write_cell as *const ());
+                asm.check_io();
+            }
+            WriteRepeat { count } => {
+                asm.bytes(&[0x42, 0x0f, 0xb6, 0x34, 0x23]); // movzx esi, byte [rbx + r12]
+                asm.bytes(&[0xba]); // mov edx, count
+                asm.imm32(imm(count as isize)?);
+                asm.call(write_repeat as *const ());
+                asm.check_io();
+            }
+            PrintInt => {
+                asm.bytes(&[0x42, 0x0f, 0xb6, 0x34, 0x23]); // movzx esi, byte [rbx + r12]
+                asm.call(print_int as *const ());
+                asm.check_io();
+            }
+            Comma => {
+                asm.bytes(&[0x42, 0x0f, 0xb6, 0x34, 0x23]); // movzx esi, byte [rbx + r12]
+                asm.call(read_cell as *const ());
+                asm.check_io();
+                asm.bytes(&[0x42, 0x88, 0x04, 0x23]); // mov byte [rbx + r12], al
+            }
+            Random => {
+                asm.call(random_cell as *const ());
+                asm.bytes(&[0x42, 0x88, 0x04, 0x23]); // mov byte [rbx + r12], al
+            }
+            Reset => {
+                asm.bytes(&[0x48, 0x89, 0xdf]); // mov rdi, rbx
+                asm.bytes(&[0x4c, 0x89, 0xf1]); // mov rcx, r14
+                asm.bytes(&[0x31, 0xc0]); // xor eax, eax
+                asm.bytes(&[0xf3, 0xaa]); // rep stosb
+                asm.bytes(&[0x49, 0xc7, 0xc4]); // mov r12, start_offset
+                asm.imm32(imm(config.start_offset as isize)?);
+            }
+            ClearRange { len: 0, .. } => {}
+            ClearRange { from, len } => {
+                asm.index(imm(from + len as isize - 1)?, pc);
+                asm.index(imm(from)?, pc);
+                asm.bytes(&[0x48, 0x8d, 0x3c, 0x03]); // lea rdi, [rbx + rax]
+                asm.bytes(&[0xb9]); // mov ecx, len
+                asm.imm32(imm(len as isize)?);
+                asm.bytes(&[0x31, 0xc0]); // xor eax, eax
+                asm.bytes(&[0xf3, 0xaa]); // rep stosb
+            }
+            Comment(_) => {}
+            Debug if config.quiet => {}
+            Debug => {
+                asm.bytes(&[0x4d, 0x89, 0x7d, 0x18]); // mov [r13 + 24], r15
+                asm.bytes(&[0x4c, 0x89, 0xe6]); // mov rsi, r12
+                asm.call(debug as *const ());
+            }
+            Halt => {
+                let at = asm.jump(&[0xe9]); // jmp exit
+                asm.halts.push(at);
+            }
+            LBracket(_) => {
+                asm.test_cell();
+                let skip = asm.jump(&[0x0f, 0x84]); // je past the loop
+                loops.push((asm.code.len(), Some(skip)));
+            }
+            LoopStart(_) => loops.push((asm.code.len(), None)),
+            RBracket(_) => {
+                let (body, skip) = loops.pop().expect("unbalanced brackets");
+                asm.test_cell();
+                let back = asm.jump(&[0x0f, 0x85]); // jne into the loop
+                asm.patch(back, body);
+                if let Some(skip) = skip {
+                    let end = asm.code.len();
+                    asm.patch(skip, end);
+                }
+            }
+        }
+    }
+    let exit = asm.code.len();
+    asm.bytes(&[0x31, 0xc0]); // xor eax, eax
+    let done = asm.jump(&[0xe9]); // jmp epilogue
+    let error = asm.code.len();
+    asm.bytes(&[0xb8]); // mov eax, IO_ERROR
+    asm.imm32(IO_ERROR as i32);
+    let error_done = asm.jump(&[0xe9]); // jmp epilogue
+    let fault = asm.code.len();
+    asm.bytes(&[0x49, 0x89, 0x4d, 0x08]); // mov [r13 + 8], rcx
+    asm.bytes(&[0x49, 0x89, 0x45, 0x10]); // mov [r13 + 16], rax
+    asm.bytes(&[0xb8]); // mov eax, OUT_OF_BOUNDS
+    asm.imm32(OUT_OF_BOUNDS as i32);
+    let epilogue = asm.code.len();
+    asm.bytes(&[0x4d, 0x89, 0x65, 0x00]); // mov [r13], r12
+    asm.bytes(&[0x4d, 0x89, 0x7d, 0x18]); // mov [r13 + 24], r15
+    asm.bytes(&[0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5b]); // pop r15-r12, rbx
+    asm.bytes(&[0xc3]); // ret
+    asm.patch(done, epilogue);
+    asm.patch(error_done, epilogue);
+    for at in std::mem::take(&mut asm.halts) {
+        asm.patch(at, exit);
+    }
+    for at in std::mem::take(&mut asm.errors) {
+        asm.patch(at, error);
+    }
+    for at in std::mem::take(&mut asm.faults) {
+        asm.patch(at, fault);
+    }
+    Ok(asm.code)
+}
+
+/// A mapping of executable machine code
+struct Executable {
+    addr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Executable {
+    fn new(code: &[u8]) -> io::Result<Self> {
+        let len = code.len();
+        // Safety: a fresh private mapping aliases nothing, and is only made
+        // executable once it holds the whole function
+        unsafe {
+            let addr = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if addr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let exe = Executable { addr, len };
+            ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, len);
+            if libc::mprotect(addr, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(exe)
+        }
+    }
+}
+
+impl Drop for Executable {
+    fn drop(&mut self) {
+        // Safety: the mapping was created by `new` and is no longer in use
+        unsafe {
+            libc::munmap(self.addr, self.len);
+        }
+    }
+}
+
+impl Interpreter {
+    /// Runs the loaded program from the start by compiling it to machine
+    /// code, producing the same output as `run`
+    ///
+    /// Only 8-bit cells and byte I/O are supported, without pointer wrapping,
+    /// statistics, profiling, step or output limits, watched cells or
+    /// recording. The tape is limited to the configured size or 65536 cells.
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.load(hello).unwrap();
+    /// let mut jitted = interp.clone();
+    /// let mut expected = Vec::new();
+    /// interp.run(io::empty(), &mut expected).unwrap();
+    /// let mut out = Vec::new();
+    /// jitted.run_jit(io::empty(), &mut out).unwrap();
+    /// assert_eq!(out, expected);
+    /// assert_eq!(out, b"Hello World!\n");
+    /// ```
+    pub fn run_jit<R: Read, W: Write>(&mut self, mut input: R, output: W) -> Result<(), BfError> {
+        self.check_config()?;
+        let config = &self.config;
+        if config.cell_size != CellSize::U8 {
+            return Err(BfError::ConfigError("the JIT only supports 8-bit cells"));
+        }
+        if config.numeric
+            || config.utf8
+            || config.escape_output
+            || config.ascii7.is_some()
+            || config.newline_at_end
+            || config.pad_output.is_some()
+            || config.input_map != IDENTITY_MAP
+            || config.output_map != IDENTITY_MAP
+        {
+            return Err(BfError::ConfigError("the JIT only supports byte I/O"));
+        }
+        if config.wrap_pointer
+            || config.stats
+            || config.profile
+            || config.max_steps != 0
+            || config.max_output != 0
+            || config.timeout.is_some()
+            || config.expect_input.is_some()
+            || !config.watch.is_empty()
+            || config.record
+            || config.sparse
+            || config.bidirectional
+        {
+            return Err(BfError::ConfigError(
+                "the JIT doesn't support pointer wrapping, stats, profiling, limits, watches, recording, sparse or bidirectional tapes",
+            ));
+        }
+        let len = config.tape_size.unwrap_or(DEFAULT_TAPE_SIZE);
+        if self.tape.len() > len {
+            return Err(BfError::ConfigError("the tape is too large for the JIT"));
+        }
+        let mut tape = vec![0u8; len];
+        for (cell, &value) in tape.iter_mut().zip(self.tape.as_slice()) {
+            *cell = value as u8;
+        }
+        let exe = Executable::new(&compile(&self.instrs, config)?)?;
+        let mut output = io::BufWriter::with_capacity(config.output_buffer, output);
+        let mut ctx = Context {
+            ptr: self.ptr,
+            fault_pc: 0,
+            fault_position: 0,
+            max_index: 0,
+            tape: tape.as_ptr(),
+            len,
+            initial_len: self.tape.len(),
+            input: &mut input,
+            output: &mut output,
+            eof: config.eof,
+            sticky_eof: config.sticky_eof,
+            at_eof: self.at_eof,
+            unbuffered: config.unbuffered,
+            rng: self.rng,
+            error: None,
+        };
+        // Safety: the code was generated for this calling convention, keeps
+        // its accesses within the tape, and only calls the callbacks above
+        let status = unsafe {
+            let f: JitFn = std::mem::transmute(exe.addr);
+            f(tape.as_mut_ptr(), len, &mut ctx)
+        };
+        let (ptr, fault_pc, fault_position) = (ctx.ptr, ctx.fault_pc, ctx.fault_position);
+        let error = ctx.error.take();
+        self.rng = ctx.rng;
+        self.at_eof = ctx.at_eof;
+        let used = used_len(ctx.max_index, self.tape.len());
+        self.tape = Tape::Dense(tape[..used].iter().map(|&c| c as u32).collect());
+        match status {
+            OK => {
+                self.ptr = ptr;
+                self.pc = self.instrs.len();
+                Ok(output.flush()?)
+            }
+            IO_ERROR => Err(error.expect("I/O error not recorded").into()),
+            OUT_OF_BOUNDS => Err(BfError::PointerOutOfBounds {
+                position: fault_position,
+                pc: fault_pc,
+            }),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BfOp::*, Config, Interpreter, REPEAT_CHUNK};
+    use std::io;
+
+    #[test]
+    fn tape_keeps_touched_zero_cells() {
+        for code in ["++>", "++>>>+<<<", "+>>>>[-]<<<<", "+[>>>+<<<-]"] {
+            let mut interp = Interpreter::new(Config::default());
+            interp.eval(code, io::empty(), io::sink()).unwrap();
+            let mut jit = Interpreter::new(Config::default());
+            jit.load(code).unwrap();
+            jit.run_jit(io::empty(), io::sink()).unwrap();
+            assert_eq!(jit.tape(), interp.tape(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn long_write_repeat_is_written_in_chunks() {
+        let count = 3 * REPEAT_CHUNK + 5;
+        let ops = vec![Add(65), WriteRepeat { count }];
+        let mut jit = Interpreter::from_ops(Config::default(), ops).unwrap();
+        let mut out = Vec::new();
+        jit.run_jit(io::empty(), &mut out).unwrap();
+        assert_eq!(out, vec![b'A'; count]);
+    }
+}
@@ -0,0 +1,3458 @@
+//! A simple brainfuck interpreter
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+pub mod bytecode;
+pub mod emit;
+pub mod format;
+#[cfg(all(feature = "jit", target_arch = "x86_64", unix))]
+pub mod jit;
+pub mod lint;
+pub mod optimize;
+pub mod preprocess;
+pub mod state;
+mod tape;
+#[cfg(feature = "memory-view")]
+pub mod view;
+
+use optimize::optimize_mapped;
+pub use optimize::{optimize, OptLevel};
+use tape::Tape;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BfOp {
+    Gt,
+    Lt,
+    Plus,
+    Minus,
+    Dot,
+    Comma,
+    LBracket(usize),
+    RBracket(usize),
+    /// Adds a (wrapping) amount to the current cell
+    Add(i32),
+    /// Moves the pointer by a signed offset
+    Move(isize),
+    /// Sets the current cell to zero
+    Clear,
+    /// Prints the tape around the pointer (`#`, only in debug mode)
+    Debug,
+    /// Moves the pointer right to the nearest zero cell (`[>]`)
+    ScanRight,
+    /// Moves the pointer left to the nearest zero cell (`[<]`)
+    ScanLeft,
+    /// Adds the current cell times `factor` to the cell at `offset`; with a
+    /// factor of 1 it moves or copies the cell and skips the multiplication
+    MulAdd {
+        offset: isize,
+        factor: i32,
+    },
+    /// Stops the program (`@`, only with extensions)
+    Halt,
+    /// Adds a (wrapping) amount to the cell at `offset` without moving
+    AddAt {
+        offset: isize,
+        val: i32,
+    },
+    /// Writes the current cell as a decimal number (`&` by default, only
+    /// with extensions)
+    PrintInt,
+    /// Stores a random byte in the current cell (`?`, only with extensions)
+    Random,
+    /// Zeroes the whole tape and moves the pointer back to the start offset
+    /// (`~`, only with extensions)
+    Reset,
+    /// Zeroes the `len` cells starting at `from` without moving, for a run
+    /// of clear loops like `[-]>[-]>[-]`
+    ClearRange {
+        from: isize,
+        len: usize,
+    },
+    /// A `[` whose loop is known to be entered, so it skips testing the
+    /// current cell; its `]` tests it as usual
+    LoopStart(usize),
+    /// Sets the current cell to a value, wrapped to the cell size (`{65}`,
+    /// only with extensions)
+    SetValue(u32),
+    /// Writes the current cell `count` times, for a run of `.`
+    WriteRepeat {
+        count: usize,
+    },
+    /// A run of non-command bytes, only kept with `keep_comments`; does
+    /// nothing when run
+    Comment(Vec<u8>),
+}
+
+#[derive(Debug, Copy, Clone, Error)]
+pub enum ParseError {
+    #[error("Unmatched left bracket at line {line}, column {col}")]
+    UnmatchedLeftBracket { line: usize, col: usize },
+    #[error("Unmatched right bracket at line {line}, column {col}")]
+    UnmatchedRightBracket { line: usize, col: usize },
+    #[error("Unexpected character {c:?} at line {line}, column {col}")]
+    UnexpectedCharacter { c: char, line: usize, col: usize },
+    #[error("Invalid literal at line {line}, column {col}; expected {{digits}}")]
+    InvalidLiteral { line: usize, col: usize },
+}
+
+#[derive(Debug, Error)]
+pub enum BfError {
+    #[error("Invalid brainfuck syntax: {0}")]
+    ParseError(#[from] ParseError),
+    #[error("Invalid configuration: {0}")]
+    ConfigError(&'static str),
+    #[error("Invalid bytecode: {0}")]
+    InvalidBytecode(&'static str),
+    #[error("Invalid saved state: {0}")]
+    InvalidState(&'static str),
+    #[error("Incompatible saved state: {0}")]
+    IncompatibleState(String),
+    #[error("Mismatched bracket at instruction {pc}")]
+    MismatchedBracket { pc: usize },
+    #[error("Runtime error: {0}")]
+    RuntimeError(#[from] io::Error),
+    #[error("Runtime error: {source} at instruction {pc}")]
+    IoError { source: io::Error, pc: usize },
+    #[error("Invalid numeric input: {0:?}")]
+    InvalidNumber(String),
+    #[error("Pointer out of bounds: cell {position} at instruction {pc}")]
+    PointerOutOfBounds { position: isize, pc: usize },
+    #[error("Step limit of {limit} instructions exceeded")]
+    StepLimitExceeded { limit: u64 },
+    #[error("Output limit of {limit} bytes exceeded")]
+    OutputLimitExceeded { limit: u64 },
+    #[error("Time limit of {limit:?} exceeded")]
+    Timeout { limit: Duration },
+    #[error("Output byte {value:#04x} at instruction {pc} is not 7-bit ASCII")]
+    NonAsciiOutput { value: u32, pc: usize },
+    #[error("Read past the {limit} bytes of expected input at instruction {pc}")]
+    UnexpectedInputRead { limit: u64, pc: usize },
+    #[error("Interrupted at instruction {pc}")]
+    Interrupted { pc: usize },
+    #[error("Wrote {written} bytes of output, more than the padded length of {len}")]
+    OutputTooLong { len: u64, written: u64 },
+    #[error(
+        "Optimized output differs at byte {offset}: expected {}, got {}",
+        describe_byte(.expected),
+        describe_byte(.found)
+    )]
+    OptimizationMismatch {
+        offset: usize,
+        expected: Option<u8>,
+        found: Option<u8>,
+    },
+}
+
+/// Names a byte of output in `OptimizationMismatch`
+fn describe_byte(byte: &Option<u8>) -> String {
+    match byte {
+        Some(b) => format!("{:#04x}", b),
+        None => "end of output".to_string(),
+    }
+}
+
+use BfOp::*;
+use ParseError::*;
+
+impl ParseError {
+    /// The 1-based line and column the error was found at
+    pub fn position(&self) -> (usize, usize) {
+        match *self {
+            UnmatchedLeftBracket { line, col }
+            | UnmatchedRightBracket { line, col }
+            | UnexpectedCharacter { line, col, .. }
+            | InvalidLiteral { line, col } => (line, col),
+        }
+    }
+
+    /// The same error at another line and column
+    pub fn at_position(self, line: usize, col: usize) -> Self {
+        match self {
+            UnmatchedLeftBracket { .. } => UnmatchedLeftBracket { line, col },
+            UnmatchedRightBracket { .. } => UnmatchedRightBracket { line, col },
+            UnexpectedCharacter { c, .. } => UnexpectedCharacter { c, line, col },
+            InvalidLiteral { .. } => InvalidLiteral { line, col },
+        }
+    }
+}
+
+impl BfError {
+    /// The instruction a runtime error happened at, if known
+    pub fn pc(&self) -> Option<usize> {
+        match *self {
+            BfError::IoError { pc, .. }
+            | BfError::PointerOutOfBounds { pc, .. }
+            | BfError::NonAsciiOutput { pc, .. }
+            | BfError::UnexpectedInputRead { pc, .. }
+            | BfError::Interrupted { pc } => Some(pc),
+            _ => None,
+        }
+    }
+
+    /// Attributes an I/O error to the instruction at `pc`
+    fn at(self, pc: usize) -> Self {
+        match self {
+            BfError::RuntimeError(source) => BfError::IoError { source, pc },
+            e => e,
+        }
+    }
+}
+
+impl BfOp {
+    /// The name of this instruction kind, without operands
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gt => "Gt",
+            Lt => "Lt",
+            Plus => "Plus",
+            Minus => "Minus",
+            Dot => "Dot",
+            Comma => "Comma",
+            LBracket(_) => "LBracket",
+            LoopStart(_) => "LoopStart",
+            SetValue(_) => "SetValue",
+            WriteRepeat { .. } => "WriteRepeat",
+            RBracket(_) => "RBracket",
+            Add(_) => "Add",
+            Move(_) => "Move",
+            Clear => "Clear",
+            Debug => "Debug",
+            ScanRight => "ScanRight",
+            ScanLeft => "ScanLeft",
+            MulAdd { .. } => "MulAdd",
+            Halt => "Halt",
+            AddAt { .. } => "AddAt",
+            PrintInt => "PrintInt",
+            Random => "Random",
+            Reset => "Reset",
+            ClearRange { .. } => "ClearRange",
+            Comment(_) => "Comment",
+        }
+    }
+}
+
+/// How `.` limits output to 7-bit ASCII
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ascii7 {
+    /// Clear the high bit of each byte
+    Mask,
+    /// Fail on bytes above 127
+    Reject,
+}
+
+/// Cell value stored by `,` on end of input
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Eof {
+    Zero,
+    MinusOne,
+    Unchanged,
+}
+
+impl FromStr for Eof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Eof::Zero),
+            "minus-one" => Ok(Eof::MinusOne),
+            "unchanged" => Ok(Eof::Unchanged),
+            _ => Err(format!("Invalid EOF behavior: {}", s)),
+        }
+    }
+}
+
+/// Width of each tape cell
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CellSize {
+    U8,
+    U16,
+    U32,
+}
+
+impl CellSize {
+    pub fn bits(self) -> u32 {
+        match self {
+            CellSize::U8 => 8,
+            CellSize::U16 => 16,
+            CellSize::U32 => 32,
+        }
+    }
+
+    /// The largest cell value, with every bit set
+    pub fn mask(self) -> u32 {
+        u32::MAX >> (32 - self.bits())
+    }
+
+    /// Interprets a cell value as a two's-complement signed number
+    pub fn to_signed(self, value: u32) -> i32 {
+        let shift = 32 - self.bits();
+        ((value << shift) as i32) >> shift
+    }
+}
+
+impl FromStr for CellSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellSize::U8),
+            "16" => Ok(CellSize::U16),
+            "32" => Ok(CellSize::U32),
+            _ => Err(format!("Invalid cell size: {}", s)),
+        }
+    }
+}
+
+/// Interpreter settings
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of cells on the tape, or `None` to grow without bound
+    pub tape_size: Option<usize>,
+    /// Wrap the pointer around the ends of the tape instead of failing
+    pub wrap_pointer: bool,
+    /// The cell the pointer starts at, leaving this many cells to its left
+    pub start_offset: usize,
+    /// Store only the cells that have been written, trading speed for memory
+    /// when a program touches a few far-apart cells
+    pub sparse: bool,
+    /// Grow the tape to the left as well as the right when the pointer moves
+    /// past its start, instead of failing; the tape can't have a size
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let config = Config {
+    ///     bidirectional: true,
+    ///     ..Config::default()
+    /// };
+    /// let mut interp = Interpreter::new(config);
+    /// let mut output = Vec::new();
+    /// interp
+    ///     .eval("<<<<<<<<<<+++++>>>>>>>>>><<<<<<<<<<.", io::empty(), &mut output)
+    ///     .unwrap();
+    /// assert_eq!(output, [5]);
+    /// assert_eq!(interp.position(), -10);
+    /// assert_eq!(interp.cell_at(interp.origin() - 10), 5);
+    /// ```
+    pub bidirectional: bool,
+    /// Width of each cell; cells wrap at this many bits
+    pub cell_size: CellSize,
+    /// Cell value after reading EOF
+    pub eof: Eof,
+    /// Once a `,` reaches EOF, give every later `,` the EOF value without
+    /// reading again
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io::{self, Read};
+    ///
+    /// /// Empty input that counts the reads attempted
+    /// struct Reads(usize);
+    ///
+    /// impl Read for Reads {
+    ///     fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+    ///         self.0 += 1;
+    ///         Ok(0)
+    ///     }
+    /// }
+    ///
+    /// let config = Config {
+    ///     sticky_eof: true,
+    ///     ..Config::default()
+    /// };
+    /// let mut reads = Reads(0);
+    /// Interpreter::new(config).eval(",,", &mut reads, io::sink()).unwrap();
+    /// assert_eq!(reads.0, 1);
+    /// ```
+    pub sticky_eof: bool,
+    /// Enable the `#` debug instruction
+    pub debug: bool,
+    /// Enable the extension instructions, see `ParseOptions::extensions`
+    pub extensions: bool,
+    /// The character for `PrintInt`, see `ParseOptions::print_int`
+    pub print_int: u8,
+    /// Reject unknown characters, see `ParseOptions::strict`
+    pub strict: bool,
+    /// Seed for the random bytes stored by `?`
+    pub seed: u64,
+    /// Read and write cells as decimal numbers instead of bytes
+    pub numeric: bool,
+    /// With `numeric`, treat cells as two's-complement signed numbers
+    pub signed: bool,
+    /// Read and write UTF-8 text; 8-bit cells hold the encoded bytes, wider
+    /// cells hold whole code points
+    pub utf8: bool,
+    /// Write bytes other than printable ASCII and whitespace as `\xNN`
+    /// escapes; doesn't apply to numeric or UTF-8 output
+    pub escape_output: bool,
+    /// The byte stored for each byte read by `,`, the identity by default;
+    /// doesn't apply to numeric or UTF-8 input
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut input_map = Config::default().input_map;
+    /// input_map[b'A' as usize] = 1;
+    /// let config = Config {
+    ///     input_map,
+    ///     ..Config::default()
+    /// };
+    /// let mut interp = Interpreter::new(config);
+    /// interp.eval(",>,", &b"AB"[..], io::sink()).unwrap();
+    /// assert_eq!((interp.cell_at(0), interp.cell_at(1)), (1, b'B' as u32));
+    /// ```
+    pub input_map: [u8; 256],
+    /// The byte written for each byte output by `.`, the identity by
+    /// default, applied before any escaping; doesn't apply to numeric or
+    /// UTF-8 output
+    pub output_map: [u8; 256],
+    /// Limit the bytes written by `.` to 7-bit ASCII, before any escaping
+    ///
+    /// ```
+    /// use brainfuck::{Ascii7, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let code = "++++++++++++[>++++++++++++++++<-]>+.";
+    /// let config = Config {
+    ///     ascii7: Some(Ascii7::Mask),
+    ///     ..Config::default()
+    /// };
+    /// let mut out = Vec::new();
+    /// Interpreter::new(config).eval(code, io::empty(), &mut out).unwrap();
+    /// assert_eq!(out, b"A");
+    /// ```
+    pub ascii7: Option<Ascii7>,
+    /// Write a newline at the end of a run whose output didn't end with one
+    pub newline_at_end: bool,
+    /// Pad the output with NULs to exactly this many bytes when the program
+    /// finishes, failing if it wrote more
+    ///
+    /// ```
+    /// use brainfuck::{BfError, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let config = Config {
+    ///     pad_output: Some(5),
+    ///     ..Config::default()
+    /// };
+    /// let mut out = Vec::new();
+    /// Interpreter::new(config.clone()).eval("+.+.", io::empty(), &mut out).unwrap();
+    /// assert_eq!(out, [1, 2, 0, 0, 0]);
+    ///
+    /// let err = Interpreter::new(config).eval("......", io::empty(), io::sink());
+    /// assert!(matches!(err, Err(BfError::OutputTooLong { len: 5, written: 6 })));
+    /// ```
+    pub pad_output: Option<u64>,
+    /// Count executed instructions by kind
+    pub stats: bool,
+    /// Count iterations of each loop
+    pub profile: bool,
+    /// Maximum number of instructions to execute, or 0 for no limit
+    pub max_steps: u64,
+    /// Maximum number of bytes to write, or 0 for no limit
+    pub max_output: u64,
+    /// Maximum wall-clock time for a run, checked every few thousand
+    /// instructions
+    pub timeout: Option<Duration>,
+    /// The number of input bytes the program should read, failing on a `,`
+    /// after that many instead of waiting for more input or reaching EOF
+    ///
+    /// ```
+    /// use brainfuck::{BfError, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let config = Config {
+    ///     expect_input: Some(1),
+    ///     ..Config::default()
+    /// };
+    /// let result = Interpreter::new(config).eval(",.,.", &b"ab"[..], io::sink());
+    /// assert!(matches!(
+    ///     result,
+    ///     Err(BfError::UnexpectedInputRead { limit: 1, pc: 2 })
+    /// ));
+    /// ```
+    pub expect_input: Option<u64>,
+    /// Cells to report changes to
+    pub watch: Vec<usize>,
+    /// Keep a journal of changes so execution can be stepped backwards
+    pub record: bool,
+    /// Optimization passes applied when loading source
+    pub opt_level: OptLevel,
+    /// Also fold runs of `.` into `WriteRepeat`, unless `opt_level` is
+    /// `O0`; see `optimize::fold_writes`
+    pub fold_io: bool,
+    /// Flush the output after every `.`
+    pub unbuffered: bool,
+    /// Bytes of output `run` collects before writing them in one go, or 0 to
+    /// write each instruction's output as it runs; collected output is also
+    /// written before each `,` and at the end
+    pub output_buffer: usize,
+    /// Suppress the output of `#` and watched cells, which otherwise goes to
+    /// stderr
+    pub quiet: bool,
+}
+
+impl Config {
+    /// The parser settings implied by this config
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            debug: self.debug,
+            extensions: self.extensions,
+            print_int: self.print_int,
+            strict: self.strict,
+            keep_comments: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tape_size: None,
+            wrap_pointer: false,
+            start_offset: 0,
+            sparse: false,
+            bidirectional: false,
+            cell_size: CellSize::U8,
+            eof: Eof::Zero,
+            sticky_eof: false,
+            debug: false,
+            extensions: false,
+            print_int: b'&',
+            strict: false,
+            seed: 0,
+            numeric: false,
+            signed: false,
+            utf8: false,
+            escape_output: false,
+            input_map: IDENTITY_MAP,
+            output_map: IDENTITY_MAP,
+            ascii7: None,
+            newline_at_end: false,
+            pad_output: None,
+            stats: false,
+            profile: false,
+            max_steps: 0,
+            max_output: 0,
+            timeout: None,
+            expect_input: None,
+            watch: Vec::new(),
+            record: false,
+            opt_level: OptLevel::O2,
+            fold_io: false,
+            unbuffered: false,
+            output_buffer: 8192,
+            quiet: false,
+        }
+    }
+}
+
+/// Settings matching a well-known interpreter
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dialect {
+    /// 30000 8-bit cells, with `,` storing 0 at end of input
+    Classic,
+    /// 30000 8-bit cells with the pointer wrapping around, leaving the cell
+    /// unchanged at end of input
+    Bff,
+    /// An unbounded tape of 8-bit cells, with `,` storing 0 at end of input
+    Urban,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(Dialect::Classic),
+            "bff" => Ok(Dialect::Bff),
+            "urban" => Ok(Dialect::Urban),
+            _ => Err(format!("Invalid dialect: {}", s)),
+        }
+    }
+}
+
+impl Dialect {
+    /// The default config with this dialect's tape, cell and EOF settings
+    pub fn config(self) -> Config {
+        let (tape_size, wrap_pointer, eof) = match self {
+            Dialect::Classic => (Some(30000), false, Eof::Zero),
+            Dialect::Bff => (Some(30000), true, Eof::Unchanged),
+            Dialect::Urban => (None, false, Eof::Zero),
+        };
+        Config {
+            tape_size,
+            wrap_pointer,
+            cell_size: CellSize::U8,
+            eof,
+            ..Config::default()
+        }
+    }
+}
+
+/// The byte map that leaves every byte unchanged
+pub const IDENTITY_MAP: [u8; 256] = {
+    let mut map = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        map[i] = i as u8;
+        i += 1;
+    }
+    map
+};
+
+/// Returns the 1-based line and column of a byte offset in `code`
+pub(crate) fn line_col(code: &[u8], offset: usize) -> (usize, usize) {
+    let before = &code[..offset];
+    let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+    let col = match before.iter().rposition(|&c| c == b'\n') {
+        Some(nl) => offset - nl,
+        None => offset + 1,
+    };
+    (line, col)
+}
+
+/// Finds which of several sources joined into `code` a 1-based line and
+/// column fall in, given the offset each source starts at, returning its
+/// index and the line and column within it
+///
+/// ```
+/// use brainfuck::{locate_part, parse};
+///
+/// // A setup file that opens a loop and a body file that closes it only
+/// // parse as one program
+/// let (setup, body) = (&b"++++\n[>++"[..], &b"+++\n<-]>.\n"[..]);
+/// assert!(parse(setup).is_err() && parse(body).is_err());
+/// let code = [setup, body].concat();
+/// assert!(parse(&code).is_ok());
+///
+/// let code = [setup, body, b"]"].concat();
+/// let pos = parse(&code).unwrap_err().position();
+/// let starts = [0, setup.len(), setup.len() + body.len()];
+/// assert_eq!(locate_part(&code, &starts, pos.0, pos.1), (2, 1, 1));
+/// ```
+pub fn locate_part(
+    code: &[u8],
+    starts: &[usize],
+    line: usize,
+    col: usize,
+) -> (usize, usize, usize) {
+    let line_start = match line {
+        1 => 0,
+        _ => code
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c == b'\n')
+            .nth(line - 2)
+            .map_or(code.len(), |(i, _)| i + 1),
+    };
+    let offset = (line_start + col.max(1) - 1).min(code.len());
+    let part = starts.iter().rposition(|&s| s <= offset).unwrap_or(0);
+    let start = starts.get(part).copied().unwrap_or(0);
+    let (line, col) = line_col(&code[start..], offset - start);
+    (part, line, col)
+}
+
+/// Renders the line of `code` around a byte offset, up to `SNIPPET_RADIUS`
+/// bytes each side, with a caret under the byte at the offset
+///
+/// ```
+/// use brainfuck::snippet;
+///
+/// assert_eq!(snippet(b"+[>\n+<-]", 5), "line 2, column 2:\n    +<-]\n     ^");
+/// ```
+pub fn snippet(code: &[u8], offset: usize) -> String {
+    let (line, col) = line_col(code, offset);
+    let start = offset + 1 - col;
+    let end = code[offset..]
+        .iter()
+        .position(|&c| c == b'\n')
+        .map_or(code.len(), |n| offset + n);
+    let from = start.max(offset.saturating_sub(SNIPPET_RADIUS));
+    let to = end.min(offset + SNIPPET_RADIUS);
+    let before = String::from_utf8_lossy(&code[from..offset]);
+    // Keep tabs so the caret lines up
+    let pad: String = before
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!(
+        "line {}, column {}:\n    {}\n    {}^",
+        line,
+        col,
+        String::from_utf8_lossy(&code[from..to]),
+        pad
+    )
+}
+
+/// How much of a long line `snippet` shows each side of the offset
+const SNIPPET_RADIUS: usize = 30;
+
+/// Options controlling which characters are recognized as instructions
+#[derive(Debug, Copy, Clone)]
+pub struct ParseOptions {
+    /// Recognize `#` as `Debug`
+    pub debug: bool,
+    /// Recognize the extension instructions: `@` as `Halt`, `print_int` as
+    /// `PrintInt`, `?` as `Random`, `~` as `Reset` and a decimal literal
+    /// in braces like `{65}` as `SetValue`
+    ///
+    /// Extension instructions may appear anywhere, including inside loops,
+    /// and don't affect bracket matching.
+    ///
+    /// ```
+    /// use brainfuck::{parse_with, BfOp::*, Config, Interpreter, ParseOptions};
+    /// use std::io;
+    ///
+    /// let opts = ParseOptions {
+    ///     extensions: true,
+    ///     ..ParseOptions::default()
+    /// };
+    /// assert_eq!(parse_with(b"{65}.", opts).unwrap(), vec![SetValue(65), Dot]);
+    /// assert!(parse_with(b"{6x}", opts).is_err());
+    ///
+    /// let config = Config { extensions: true, ..Config::default() };
+    /// let mut out = Vec::new();
+    /// let mut interp = Interpreter::new(config);
+    /// interp.eval("{65}.>{300}", io::empty(), &mut out).unwrap();
+    /// assert_eq!((out, interp.cell_at(1)), (b"A".to_vec(), 44));
+    /// ```
+    pub extensions: bool,
+    /// The character for `PrintInt`, `&` by default
+    pub print_int: u8,
+    /// With `extensions`, reject bytes other than commands and whitespace
+    /// instead of ignoring them, to catch mistyped extension characters
+    ///
+    /// ```
+    /// use brainfuck::{parse_with, ParseOptions};
+    ///
+    /// let opts = ParseOptions {
+    ///     extensions: true,
+    ///     ..ParseOptions::default()
+    /// };
+    /// assert!(parse_with(b"+$.", opts).is_ok());
+    /// let strict = ParseOptions { strict: true, ..opts };
+    /// let err = parse_with(b"+\n $.", strict).unwrap_err();
+    /// assert_eq!(err.to_string(), "Unexpected character '$' at line 2, column 2");
+    /// ```
+    pub strict: bool,
+    /// Keep each run of other bytes as a `Comment` instead of discarding it
+    pub keep_comments: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            debug: false,
+            extensions: false,
+            print_int: b'&',
+            strict: false,
+            keep_comments: false,
+        }
+    }
+}
+
+/// Parses brainfuck source into instructions with matched bracket offsets
+pub fn parse(code: &[u8]) -> Result<Vec<BfOp>, ParseError> {
+    parse_with(code, ParseOptions::default())
+}
+
+/// Parses brainfuck source, recognizing the extra instructions enabled in `opts`
+pub fn parse_with(code: &[u8], opts: ParseOptions) -> Result<Vec<BfOp>, ParseError> {
+    Ok(parse_offsets(code, opts)?.0)
+}
+
+/// Like `parse_with`, also returning the source byte offset of each
+/// instruction, for mapping instructions back to the source
+pub fn parse_offsets(
+    code: &[u8],
+    opts: ParseOptions,
+) -> Result<(Vec<BfOp>, Vec<usize>), ParseError> {
+    let mut i = 0;
+    while i < code.len() {
+        match literal(code, i, opts) {
+            Some(Some((_, end))) => {
+                i = end;
+                continue;
+            }
+            Some(None) => {
+                let (line, col) = line_col(code, i);
+                return Err(InvalidLiteral { line, col });
+            }
+            None if unexpected(code[i], opts) => {
+                let (line, col) = line_col(code, i);
+                let c = code[i] as char;
+                return Err(UnexpectedCharacter { c, line, col });
+            }
+            None => i += 1,
+        }
+    }
+    let (mut instrs, offsets) = tokenize_offsets(code, opts);
+    match_brackets(&mut instrs, |i| line_col(code, offsets[i]))?;
+    Ok((instrs, offsets))
+}
+
+/// Converts source to instructions, ignoring non-command bytes and leaving
+/// bracket offsets unresolved
+pub fn tokenize(code: &[u8]) -> Vec<BfOp> {
+    tokenize_with(code, ParseOptions::default())
+}
+
+/// Like `tokenize`, recognizing the extra instructions enabled in `opts`
+pub fn tokenize_with(code: &[u8], opts: ParseOptions) -> Vec<BfOp> {
+    tokenize_offsets(code, opts).0
+}
+
+/// The instruction for a source byte, if it is a command
+fn token(c: u8, opts: ParseOptions) -> Option<BfOp> {
+    Some(match c {
+        b'>' => Gt,
+        b'<' => Lt,
+        b'+' => Plus,
+        b'-' => Minus,
+        b'.' => Dot,
+        b',' => Comma,
+        b'[' => LBracket(0),
+        b']' => RBracket(0),
+        b'#' if opts.debug => Debug,
+        b'@' if opts.extensions => Halt,
+        c if opts.extensions && c == opts.print_int => PrintInt,
+        b'?' if opts.extensions => Random,
+        b'~' if opts.extensions => Reset,
+        _ => return None,
+    })
+}
+
+/// The value of the `SetValue` literal starting at `i` and the offset past
+/// its `}`, `Some(None)` if it is malformed, or `None` if there is no
+/// literal there
+fn literal(code: &[u8], i: usize, opts: ParseOptions) -> Option<Option<(u32, usize)>> {
+    if !opts.extensions || code[i] != b'{' {
+        return None;
+    }
+    let digits = code[i + 1..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    let end = i + 1 + digits;
+    if digits == 0 || code.get(end) != Some(&b'}') {
+        return Some(None);
+    }
+    let value = code[i + 1..end].iter().try_fold(0, push_digit);
+    Some(value.map(|v| (v, end + 1)))
+}
+
+/// Appends a decimal digit to a literal's value, or `None` if it no longer
+/// fits in 32 bits
+fn push_digit(value: u32, digit: &u8) -> Option<u32> {
+    value.checked_mul(10)?.checked_add((digit - b'0') as u32)
+}
+
+/// Whether `strict` parsing rejects a source byte
+fn unexpected(c: u8, opts: ParseOptions) -> bool {
+    opts.strict && opts.extensions && !c.is_ascii_whitespace() && token(c, opts).is_none()
+}
+
+/// Like `tokenize_with`, also returning the source byte offset of each
+/// instruction; a `Comment` is at the offset of its first byte
+///
+/// ```
+/// use brainfuck::{tokenize_offsets, BfOp::*, ParseOptions};
+///
+/// let (instrs, offsets) = tokenize_offsets(b"xx+", ParseOptions::default());
+/// assert_eq!((instrs, offsets), (vec![Plus], vec![2]));
+/// ```
+pub fn tokenize_offsets(code: &[u8], opts: ParseOptions) -> (Vec<BfOp>, Vec<usize>) {
+    let (mut instrs, mut offsets) = (Vec::new(), Vec::new());
+    let mut i = 0;
+    while i < code.len() {
+        let c = code[i];
+        // Malformed literals are left as comments
+        if let Some(Some((value, end))) = literal(code, i, opts) {
+            instrs.push(SetValue(value));
+            offsets.push(i);
+            i = end;
+            continue;
+        }
+        match token(c, opts) {
+            Some(op) => instrs.push(op),
+            None if !opts.keep_comments || push_comment(&mut instrs, c) => {
+                i += 1;
+                continue;
+            }
+            None => {}
+        }
+        offsets.push(i);
+        i += 1;
+    }
+    (instrs, offsets)
+}
+
+/// Appends a non-command byte to the trailing comment, returning false if
+/// it starts a new one
+fn push_comment(instrs: &mut Vec<BfOp>, c: u8) -> bool {
+    match instrs.last_mut() {
+        Some(Comment(text)) => {
+            text.push(c);
+            true
+        }
+        _ => {
+            instrs.push(Comment(vec![c]));
+            false
+        }
+    }
+}
+
+/// Parses brainfuck source from a reader a chunk at a time, so the source is
+/// never held in memory all at once
+pub fn parse_reader<R: Read>(reader: R) -> Result<Vec<BfOp>, BfError> {
+    parse_reader_with(reader, ParseOptions::default())
+}
+
+/// Like `parse_reader`, recognizing the extra instructions enabled in `opts`
+pub fn parse_reader_with<R: Read>(reader: R, opts: ParseOptions) -> Result<Vec<BfOp>, BfError> {
+    Ok(parse_reader_offsets(reader, opts)?.0)
+}
+
+/// Parses source from a reader, also returning the byte offset of each
+/// instruction
+pub(crate) fn parse_reader_offsets<R: Read>(
+    mut reader: R,
+    opts: ParseOptions,
+) -> Result<(Vec<BfOp>, Vec<usize>), BfError> {
+    let (mut instrs, mut offsets) = (Vec::new(), Vec::new());
+    let mut pos = 0;
+    // Indices, lines and columns of the open brackets
+    let mut brackets: Vec<(usize, usize, usize)> = Vec::new();
+    let (mut line, mut col) = (1, 0);
+    // The value so far, offset, line and column of an unfinished literal
+    let mut literal: Option<(Option<u32>, usize, usize, usize)> = None;
+    let mut buf = [0; 8192];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for &c in &buf[..n] {
+            if c == b'\n' {
+                line += 1;
+                col = 0;
+            } else {
+                col += 1;
+            }
+            let i = instrs.len();
+            if let Some((value, start, line, col)) = literal {
+                literal = match (c, value) {
+                    (b'0'..=b'9', _) => match push_digit(value.unwrap_or(0), &c) {
+                        Some(v) => Some((Some(v), start, line, col)),
+                        None => return Err(InvalidLiteral { line, col }.into()),
+                    },
+                    (b'}', Some(v)) => {
+                        instrs.push(SetValue(v));
+                        offsets.push(start);
+                        None
+                    }
+                    _ => return Err(InvalidLiteral { line, col }.into()),
+                };
+                pos += 1;
+                continue;
+            }
+            match token(c, opts) {
+                None if c == b'{' && opts.extensions => {
+                    literal = Some((None, pos, line, col));
+                }
+                Some(LBracket(_)) => {
+                    brackets.push((i, line, col));
+                    instrs.push(LBracket(0));
+                }
+                Some(RBracket(_)) => {
+                    let (j, _, _) = brackets.pop().ok_or(UnmatchedRightBracket { line, col })?;
+                    instrs[j] = LBracket(i);
+                    instrs.push(RBracket(j));
+                }
+                Some(op) => instrs.push(op),
+                None if unexpected(c, opts) => {
+                    let c = c as char;
+                    return Err(UnexpectedCharacter { c, line, col }.into());
+                }
+                None if opts.keep_comments => {
+                    push_comment(&mut instrs, c);
+                }
+                None => (),
+            }
+            if offsets.len() < instrs.len() {
+                offsets.push(pos);
+            }
+            pos += 1;
+        }
+    }
+    if let Some((_, _, line, col)) = literal {
+        return Err(InvalidLiteral { line, col }.into());
+    }
+    if let Some(&(_, line, col)) = brackets.last() {
+        return Err(UnmatchedLeftBracket { line, col }.into());
+    }
+    Ok((instrs, offsets))
+}
+
+/// Sets the offsets of each pair of brackets to point at each other
+///
+/// Since there is no source to refer to, errors report the instruction index
+/// as the column of line 1; use `parse` for source positions.
+pub fn resolve_brackets(instrs: &mut [BfOp]) -> Result<(), ParseError> {
+    match_brackets(instrs, |i| (1, i + 1))
+}
+
+/// Checks that the offset of every bracket points at its matching bracket,
+/// as `resolve_brackets` would set it
+///
+/// ```
+/// use brainfuck::{validate_ops, BfError, BfOp::*};
+///
+/// assert!(validate_ops(&[LBracket(2), Plus, RBracket(0)]).is_ok());
+/// let crossed = [LBracket(2), LBracket(3), RBracket(0), RBracket(1)];
+/// assert!(matches!(
+///     validate_ops(&crossed),
+///     Err(BfError::MismatchedBracket { pc: 2 })
+/// ));
+/// ```
+pub fn validate_ops(instrs: &[BfOp]) -> Result<(), BfError> {
+    let mut brackets = Vec::new();
+    for (i, op) in instrs.iter().enumerate() {
+        let valid = match *op {
+            LBracket(j) | LoopStart(j) => {
+                brackets.push(i);
+                matches!(instrs.get(j), Some(&RBracket(k)) if k == i)
+            }
+            RBracket(j) => brackets.pop() == Some(j),
+            _ => true,
+        };
+        if !valid {
+            return Err(BfError::MismatchedBracket { pc: i });
+        }
+    }
+    match brackets.last() {
+        Some(&pc) => Err(BfError::MismatchedBracket { pc }),
+        None => Ok(()),
+    }
+}
+
+/// Resolves brackets, using `locate` to find the line and column of an
+/// unmatched bracket from its index
+pub(crate) fn match_brackets<F>(instrs: &mut [BfOp], locate: F) -> Result<(), ParseError>
+where
+    F: Fn(usize) -> (usize, usize),
+{
+    let mut brackets = Vec::new();
+    for i in 0..instrs.len() {
+        match instrs[i] {
+            LBracket(_) | LoopStart(_) => brackets.push(i),
+            RBracket(_) => {
+                let j = brackets.pop().ok_or_else(|| {
+                    let (line, col) = locate(i);
+                    UnmatchedRightBracket { line, col }
+                })?;
+                instrs[j] = match instrs[j] {
+                    LoopStart(_) => LoopStart(i),
+                    _ => LBracket(i),
+                };
+                instrs[i] = RBracket(j);
+            }
+            _ => (),
+        }
+    }
+    if let Some(&j) = brackets.last() {
+        let (line, col) = locate(j);
+        return Err(UnmatchedLeftBracket { line, col });
+    }
+    Ok(())
+}
+
+/// A writer that copies everything written to the first writer to the
+/// second as well
+///
+/// ```
+/// use brainfuck::{Config, Interpreter, Tee};
+/// use std::io;
+///
+/// let (mut primary, mut copy) = (Vec::new(), Vec::new());
+/// Interpreter::new(Config::default())
+///     .eval("+++.+.", io::empty(), Tee(&mut primary, &mut copy))
+///     .unwrap();
+/// assert_eq!(primary, [3, 4]);
+/// assert_eq!(copy, [3, 4]);
+/// ```
+#[derive(Debug)]
+pub struct Tee<A, B>(pub A, pub B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// A reader that copies every byte read from the first reader to a writer,
+/// so the input a program consumed can be fed to it again
+///
+/// ```
+/// use brainfuck::{Config, Interpreter, TeeReader};
+/// use std::io;
+///
+/// let cat = ",[.,]";
+/// let (mut output, mut log) = (Vec::new(), Vec::new());
+/// Interpreter::new(Config::default())
+///     .eval(cat, TeeReader(&b"hello"[..], &mut log), &mut output)
+///     .unwrap();
+/// assert_eq!(log, b"hello");
+///
+/// let mut replayed = Vec::new();
+/// Interpreter::new(Config::default())
+///     .eval(cat, &log[..], &mut replayed)
+///     .unwrap();
+/// assert_eq!(replayed, output);
+/// ```
+#[derive(Debug)]
+pub struct TeeReader<R, W>(pub R, pub W);
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        self.1.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+/// A reader that reads a whole line from the inner reader once the last
+/// one has been used up, handing it out a byte at a time with its newline
+///
+/// ```
+/// use brainfuck::{Config, Interpreter, LineReader};
+///
+/// let input = LineReader::new(&b"one\ntwo\n"[..]);
+/// let mut output = Vec::new();
+/// Interpreter::new(Config::default())
+///     .eval(",[.,]", input, &mut output)
+///     .unwrap();
+/// assert_eq!(output, b"one\ntwo\n");
+/// ```
+#[derive(Debug)]
+pub struct LineReader<R> {
+    inner: R,
+    line: Vec<u8>,
+    /// The number of bytes of `line` already read
+    pos: usize,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(inner: R) -> Self {
+        LineReader {
+            inner,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for LineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            self.inner.read_until(b'\n', &mut self.line)?;
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Whether a byte is printable ASCII or common whitespace
+fn is_printable(byte: u8) -> bool {
+    byte.is_ascii_graphic() || matches!(byte, b' ' | b'\n' | b'\t' | b'\r')
+}
+
+/// Advances a splitmix64 generator, returning the top byte of its output
+pub(crate) fn random_byte(state: &mut u64) -> u8 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    ((z ^ (z >> 31)) >> 56) as u8
+}
+
+/// Reads one byte, retrying interrupted reads, or `None` at end of input
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads a whitespace-delimited decimal number, wrapping on overflow, or
+/// `None` at end of input; negative numbers are allowed if `signed`
+fn read_number<R: Read>(input: &mut R, signed: bool) -> Result<Option<u32>, BfError> {
+    let mut token = Vec::new();
+    while let Some(byte) = read_byte(input)? {
+        if !byte.is_ascii_whitespace() {
+            token.push(byte);
+        } else if !token.is_empty() {
+            break;
+        }
+    }
+    if token.is_empty() {
+        return Ok(None);
+    }
+    let digits = match token.strip_prefix(b"-") {
+        Some(digits) if signed => digits,
+        _ => &token,
+    };
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        let token = String::from_utf8_lossy(&token).into_owned();
+        return Err(BfError::InvalidNumber(token));
+    }
+    let n = digits.iter().fold(0u32, |n, &d| {
+        n.wrapping_mul(10).wrapping_add((d - b'0') as u32)
+    });
+    Ok(Some(match digits.len() < token.len() {
+        true => n.wrapping_neg(),
+        false => n,
+    }))
+}
+
+/// Execution counters collected while running
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Total instructions executed
+    pub steps: u64,
+    /// Instructions executed per kind, if enabled in the config
+    pub ops: BTreeMap<&'static str, u64>,
+    /// Number of jumps back to the start of a loop
+    pub loop_iterations: u64,
+    /// Bytes written by `.`
+    pub output_bytes: u64,
+    /// Bytes read by `,`
+    pub input_bytes: u64,
+    /// Jumps back per loop, keyed by the index of its `[`, if profiling is
+    /// enabled in the config
+    pub loops: BTreeMap<usize, u64>,
+}
+
+impl Stats {
+    /// The profiled loops, the most iterated first
+    pub fn hot_loops(&self) -> Vec<(usize, u64)> {
+        let mut loops: Vec<_> = self.loops.iter().map(|(&i, &n)| (i, n)).collect();
+        loops.sort_by_key(|&(i, n)| (std::cmp::Reverse(n), i));
+        loops
+    }
+
+    /// Formats the counters as a JSON object, with `loops` keyed by the
+    /// index of each loop's `[` as a string
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.eval("++.", io::empty(), io::sink()).unwrap();
+    /// let json = interp.stats().to_json();
+    /// assert!(json.starts_with(r#"{"steps":2,"ops":{},"#));
+    /// ```
+    pub fn to_json(&self) -> String {
+        let ops: Vec<_> = self
+            .ops
+            .iter()
+            .map(|(name, n)| format!("\"{}\":{}", name, n))
+            .collect();
+        let loops: Vec<_> = self
+            .loops
+            .iter()
+            .map(|(i, n)| format!("\"{}\":{}", i, n))
+            .collect();
+        format!(
+            "{{\"steps\":{},\"ops\":{{{}}},\"loop_iterations\":{},\"output_bytes\":{},\"input_bytes\":{},\"loops\":{{{}}}}}",
+            self.steps,
+            ops.join(","),
+            self.loop_iterations,
+            self.output_bytes,
+            self.input_bytes,
+            loops.join(",")
+        )
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Executed {} instructions", self.steps)?;
+        for (name, count) in &self.ops {
+            writeln!(f, "  {:<10} {}", name, count)?;
+        }
+        writeln!(f, "Loop iterations: {}", self.loop_iterations)?;
+        writeln!(f, "Output bytes: {}", self.output_bytes)?;
+        write!(f, "Input bytes: {}", self.input_bytes)
+    }
+}
+
+/// Counters for a single call to `Interpreter::run`
+///
+/// ```
+/// use brainfuck::{Config, Interpreter};
+/// use std::io;
+///
+/// let mut interp = Interpreter::new(Config::default());
+/// let stats = interp.eval("+.>+.<.", io::empty(), io::sink()).unwrap();
+/// assert_eq!((stats.steps, stats.bytes_written), (7, 3));
+/// assert_eq!((stats.bytes_read, stats.max_ptr), (0, 1));
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    /// Instructions executed
+    pub steps: u64,
+    /// Bytes written by `.`
+    pub bytes_written: u64,
+    /// Bytes read by `,`
+    pub bytes_read: u64,
+    /// The highest cell the pointer reached
+    pub max_ptr: usize,
+}
+
+/// A reader counting the bytes read through it
+struct CountingReader<'a, R> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// How many instructions run between checks of the timeout
+const TIMEOUT_INTERVAL: u64 = 4096;
+
+/// Bytes `WriteRepeat` writes at a time, so a long run doesn't need its
+/// whole output in memory
+pub(crate) const REPEAT_CHUNK: usize = 8192;
+
+/// Resource limits for `run_bounded`, where 0 or `None` means no limit
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Limits {
+    /// Maximum number of instructions to execute
+    pub max_steps: u64,
+    /// Maximum number of bytes to write
+    pub max_output: u64,
+    /// Maximum number of cells on the tape
+    pub max_cells: Option<usize>,
+    /// Maximum wall-clock time to run for
+    pub timeout: Option<Duration>,
+}
+
+/// How a program run by `run_bounded` stopped
+#[derive(Debug)]
+pub enum Outcome {
+    Completed,
+    StepLimit,
+    OutputLimit,
+    Timeout,
+    /// Any other error, including a parse error or the pointer leaving a tape
+    /// limited by `max_cells`
+    Error(BfError),
+}
+
+/// Parses and runs a program under `limits` with the default config,
+/// returning how it stopped and the output it wrote until then
+///
+/// ```
+/// use brainfuck::{run_bounded, Limits, Outcome};
+/// use std::time::Duration;
+///
+/// let (outcome, output) = run_bounded(b",[.,]", b"hi", Limits::default());
+/// assert!(matches!(outcome, Outcome::Completed));
+/// assert_eq!(output, b"hi");
+///
+/// let steps = Limits { max_steps: 100, ..Limits::default() };
+/// assert!(matches!(run_bounded(b"+[]", b"", steps).0, Outcome::StepLimit));
+///
+/// let output = Limits { max_output: 10, ..Limits::default() };
+/// let (outcome, written) = run_bounded(b"+[.]", b"", output);
+/// assert!(matches!(outcome, Outcome::OutputLimit));
+/// assert_eq!(written.len(), 10);
+///
+/// let timeout = Limits { timeout: Some(Duration::from_millis(10)), ..Limits::default() };
+/// assert!(matches!(run_bounded(b"+[]", b"", timeout).0, Outcome::Timeout));
+///
+/// let cells = Limits { max_cells: Some(100), ..Limits::default() };
+/// assert!(matches!(run_bounded(b"+[>+]", b"", cells).0, Outcome::Error(_)));
+/// ```
+pub fn run_bounded(code: &[u8], input: &[u8], limits: Limits) -> (Outcome, Vec<u8>) {
+    let config = Config {
+        tape_size: limits.max_cells,
+        max_steps: limits.max_steps,
+        max_output: limits.max_output,
+        timeout: limits.timeout,
+        ..Config::default()
+    };
+    let mut output = Vec::new();
+    let outcome = match Interpreter::new(config).eval(code, input, &mut output) {
+        Ok(_) => Outcome::Completed,
+        Err(BfError::StepLimitExceeded { .. }) => Outcome::StepLimit,
+        Err(BfError::OutputLimitExceeded { .. }) => Outcome::OutputLimit,
+        Err(BfError::Timeout { .. }) => Outcome::Timeout,
+        Err(e) => Outcome::Error(e),
+    };
+    (outcome, output)
+}
+
+/// Runs parsed instructions on a fresh tape with the given streams
+///
+/// ```
+/// use brainfuck::{execute, parse, Config};
+///
+/// let mut input = &b"cat"[..];
+/// let mut output = Vec::new();
+/// let instrs = parse(b",[.,]").unwrap();
+/// execute(&instrs, &mut input, &mut output, &Config::default()).unwrap();
+/// assert_eq!(output, b"cat");
+/// ```
+pub fn execute<R: Read, W: Write>(
+    instrs: &[BfOp],
+    input: &mut R,
+    output: &mut W,
+    config: &Config,
+) -> Result<RunStats, BfError> {
+    let mut interp = Interpreter::new(config.clone());
+    interp.load_ops(instrs.to_vec());
+    interp.run(input, output)
+}
+
+/// Timings from running a program several times with `bench`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bench {
+    /// The time taken by each run, in the order they ran
+    pub samples: Vec<Duration>,
+}
+
+impl Bench {
+    /// The fastest run
+    pub fn min(&self) -> Duration {
+        self.samples.iter().copied().min().unwrap_or_default()
+    }
+
+    /// The middle run, or the mean of the middle two
+    pub fn median(&self) -> Duration {
+        let mut sorted = self.samples.clone();
+        sorted.sort();
+        match sorted.len() {
+            0 => Duration::ZERO,
+            n if n % 2 == 1 => sorted[n / 2],
+            n => (sorted[n / 2 - 1] + sorted[n / 2]) / 2,
+        }
+    }
+
+    /// The mean time of a run
+    pub fn mean(&self) -> Duration {
+        match self.samples.len() {
+            0 => Duration::ZERO,
+            n => self.samples.iter().sum::<Duration>() / n as u32,
+        }
+    }
+}
+
+impl fmt::Display for Bench {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} samples: min {:?}, median {:?}, mean {:?}",
+            self.samples.len(),
+            self.min(),
+            self.median(),
+            self.mean()
+        )
+    }
+}
+
+/// Runs parsed instructions `iterations` times with `execute`, each on a
+/// fresh tape reading the same input, discarding the output and timing
+/// each run
+///
+/// ```
+/// use brainfuck::{bench, optimize, parse, Config};
+///
+/// let config = Config::default();
+/// let instrs = optimize(parse(b",[.,]").unwrap(), config.opt_level);
+/// let bench = bench(&instrs, b"echo", &config, 3).unwrap();
+/// assert_eq!(bench.samples.len(), 3);
+/// assert!(bench.min() <= bench.median());
+/// assert!(bench.to_string().starts_with("3 samples: min "));
+/// ```
+pub fn bench(
+    instrs: &[BfOp],
+    input: &[u8],
+    config: &Config,
+    iterations: usize,
+) -> Result<Bench, BfError> {
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        execute(instrs, &mut &input[..], &mut io::sink(), config)?;
+        samples.push(start.elapsed());
+    }
+    Ok(Bench { samples })
+}
+
+/// Runs unoptimized and optimized versions of a program on the same input,
+/// returning the output if they wrote the same bytes or an
+/// `OptimizationMismatch` at the first byte they differ
+///
+/// ```
+/// use brainfuck::{check_optimization, optimize, parse, BfError, BfOp, Config, OptLevel};
+///
+/// let (naive, config) = (parse(b"+++.[-]+.").unwrap(), Config::default());
+/// let optimized = optimize(naive.clone(), OptLevel::O2);
+/// let output = check_optimization(&naive, &optimized, b"", &config).unwrap();
+/// assert_eq!(output, [3, 1]);
+///
+/// // A broken optimization that drops the final +
+/// let broken = vec![BfOp::Add(3), BfOp::Dot, BfOp::Clear, BfOp::Dot];
+/// let err = check_optimization(&naive, &broken, b"", &config).unwrap_err();
+/// assert!(matches!(
+///     err,
+///     BfError::OptimizationMismatch { offset: 1, expected: Some(1), found: Some(0) }
+/// ));
+/// ```
+pub fn check_optimization(
+    naive: &[BfOp],
+    optimized: &[BfOp],
+    input: &[u8],
+    config: &Config,
+) -> Result<Vec<u8>, BfError> {
+    let (mut expected, mut found) = (Vec::new(), Vec::new());
+    execute(naive, &mut &input[..], &mut expected, config)?;
+    execute(optimized, &mut &input[..], &mut found, config)?;
+    if let Some(offset) =
+        (0..expected.len().max(found.len())).find(|&i| expected.get(i) != found.get(i))
+    {
+        return Err(BfError::OptimizationMismatch {
+            offset,
+            expected: expected.get(offset).copied(),
+            found: found.get(offset).copied(),
+        });
+    }
+    Ok(found)
+}
+
+/// Runs a program with the default config on the given input, returning its
+/// output decoded as (lossy) UTF-8
+///
+/// ```
+/// use brainfuck::eval;
+///
+/// assert_eq!(eval("+++.", "").unwrap().as_bytes(), &[3]);
+/// assert_eq!(eval(",[.,]", "echo").unwrap(), "echo");
+///
+/// let hello = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+/// assert_eq!(eval(hello, "").unwrap(), "Hello World!\n");
+/// ```
+pub fn eval(code: &str, input: &str) -> Result<String, BfError> {
+    let config = Config::default();
+    let instrs = optimize(parse(code.as_bytes())?, config.opt_level);
+    let mut output = Vec::new();
+    execute(&instrs, &mut input.as_bytes(), &mut output, &config)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Reads one UTF-8 encoded character, replacing invalid sequences with
+/// U+FFFD, or `None` at end of input
+fn read_char<R: Read>(input: &mut R) -> io::Result<Option<char>> {
+    let mut buf = [0; 4];
+    buf[0] = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let len = match buf[0] {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Some(char::REPLACEMENT_CHARACTER)),
+    };
+    for byte in &mut buf[1..len] {
+        *byte = match read_byte(input)? {
+            Some(byte) => byte,
+            None => return Ok(Some(char::REPLACEMENT_CHARACTER)),
+        };
+    }
+    Ok(Some(match std::str::from_utf8(&buf[..len]) {
+        Ok(s) => s.chars().next().unwrap(),
+        Err(_) => char::REPLACEMENT_CHARACTER,
+    }))
+}
+
+/// Formats the cells around `ptr` of a tape of `len` cells for the `#`
+/// instruction, with the current cell in brackets
+fn debug_window<T: fmt::Display>(len: usize, ptr: usize, cell: impl Fn(usize) -> T) -> String {
+    let cells = ptr.saturating_sub(4)..len.min(ptr + 5);
+    let window: Vec<_> = cells
+        .map(|i| match i {
+            i if i == ptr => format!("[{}]", cell(i)),
+            i => cell(i).to_string(),
+        })
+        .collect();
+    format!("#: ptr={} | {}", ptr, window.join(" "))
+}
+
+/// Whether a program stopped by `Interpreter::run_for` can continue
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RunState {
+    /// The program ran to the end
+    Finished,
+    /// The program used up its instructions and has more to run
+    Running,
+}
+
+/// The state in which an instruction was executed, yielded by
+/// `Interpreter::steps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepInfo {
+    pub pc: usize,
+    pub ptr: usize,
+    pub op: BfOp,
+    /// The value of the current cell before the instruction ran
+    pub cell: u32,
+}
+
+/// The state before a recorded instruction, restored by `step_back`
+#[derive(Debug, Clone)]
+struct JournalEntry {
+    pc: usize,
+    ptr: usize,
+    /// The index and previous value of the cell the instruction may change
+    cell: Option<(usize, u32)>,
+    /// The whole tape before a `Reset`
+    tape: Option<Tape>,
+}
+
+/// A brainfuck interpreter holding a program and its tape
+///
+/// Cells are stored as `u32` and masked to the configured cell size, so `.`
+/// and `,` operate on the low byte of wider cells.
+///
+/// ```
+/// use brainfuck::{Config, Interpreter};
+/// use std::io;
+///
+/// let mut out = Vec::new();
+/// Interpreter::new(Config::default())
+///     .eval("++++++++[>++++++++<-]>+.", io::empty(), &mut out)
+///     .unwrap();
+/// assert_eq!(out, b"A");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    config: Config,
+    instrs: Vec<BfOp>,
+    tape: Tape,
+    pc: usize,
+    ptr: usize,
+    stats: Stats,
+    /// Bytes of an incomplete UTF-8 sequence written by `.`
+    utf8_buf: Vec<u8>,
+    journal: Vec<JournalEntry>,
+    /// The last byte written by an instruction, for `newline_at_end`
+    last_output: Option<u8>,
+    /// State of the generator for `?`
+    rng: u64,
+    /// Instructions at which `run_stepping` pauses
+    breakpoints: BTreeSet<usize>,
+    /// The source byte offset of each instruction, or empty if it wasn't
+    /// loaded from source
+    source_map: Vec<usize>,
+    /// The highest cell the pointer has reached during the current run
+    max_ptr: usize,
+    /// When the current run times out
+    deadline: Option<Instant>,
+    /// Whether a `,` has reached EOF, with `sticky_eof`
+    at_eof: bool,
+    /// The index of the cell that started as cell 0, which moves right when
+    /// a bidirectional tape grows to the left
+    origin: usize,
+    /// A flag that stops the program when set, such as from a signal handler
+    interrupt: Option<&'static AtomicBool>,
+}
+
+impl Interpreter {
+    pub fn new(config: Config) -> Self {
+        Interpreter {
+            tape: Tape::new(config.start_offset + 1, config.sparse),
+            ptr: config.start_offset,
+            rng: config.seed,
+            config,
+            instrs: Vec::new(),
+            pc: 0,
+            stats: Stats::default(),
+            utf8_buf: Vec::new(),
+            journal: Vec::new(),
+            last_output: None,
+            breakpoints: BTreeSet::new(),
+            source_map: Vec::new(),
+            max_ptr: 0,
+            at_eof: false,
+            origin: 0,
+            interrupt: None,
+            deadline: None,
+        }
+    }
+
+    /// Creates an interpreter running instructions built without source,
+    /// checking their bracket offsets with `validate_ops`
+    ///
+    /// ```
+    /// use brainfuck::{BfOp::*, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let ops = vec![Add(65), Dot];
+    /// let mut interp = Interpreter::from_ops(Config::default(), ops).unwrap();
+    /// let mut out = Vec::new();
+    /// interp.run(io::empty(), &mut out).unwrap();
+    /// assert_eq!(out, b"A");
+    ///
+    /// let ops = vec![Plus, LBracket(3), Minus, RBracket(0)];
+    /// assert!(Interpreter::from_ops(Config::default(), ops).is_err());
+    /// ```
+    pub fn from_ops(config: Config, instrs: Vec<BfOp>) -> Result<Self, BfError> {
+        validate_ops(&instrs)?;
+        let mut interp = Interpreter::new(config);
+        interp.load_ops(instrs);
+        Ok(interp)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn instrs(&self) -> &[BfOp] {
+        &self.instrs
+    }
+
+    /// The cells up to the highest one touched, or nothing with a sparse
+    /// tape; see `cell_at`
+    pub fn tape(&self) -> &[u32] {
+        self.tape.as_slice()
+    }
+
+    /// The value of the cell at index `i`
+    pub fn cell_at(&self, i: usize) -> u32 {
+        self.tape.get(i)
+    }
+
+    /// The number of cells held in memory, which for a sparse tape is the
+    /// number of cells that have been written
+    ///
+    /// ```
+    /// use brainfuck::{BfOp::*, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let config = Config {
+    ///     sparse: true,
+    ///     ..Config::default()
+    /// };
+    /// let ops = vec![Plus, Move(1_000_000), Add(2)];
+    /// let mut interp = Interpreter::from_ops(config, ops).unwrap();
+    /// interp.run(io::empty(), io::sink()).unwrap();
+    /// assert_eq!((interp.cell_at(0), interp.cell_at(1_000_000)), (1, 2));
+    /// assert_eq!(interp.stored_cells(), 2);
+    /// ```
+    pub fn stored_cells(&self) -> usize {
+        self.tape.stored()
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    /// The index in the tape of the cell that started as cell 0, which is
+    /// only nonzero once a bidirectional tape has grown to the left
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// The pointer relative to the cell that started as cell 0, which is
+    /// negative left of it on a bidirectional tape
+    pub fn position(&self) -> isize {
+        self.ptr as isize - self.origin as isize
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Clears the tape, pointer, statistics and other run state back to how
+    /// `new` left them, keeping the loaded program and breakpoints
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.eval("+>++", io::empty(), io::sink()).unwrap();
+    /// interp.reset();
+    /// assert_eq!((interp.tape(), interp.ptr(), interp.pc()), (&[0][..], 0, 0));
+    /// ```
+    pub fn reset(&mut self) {
+        let fresh = Interpreter::new(self.config.clone());
+        self.tape = fresh.tape;
+        self.ptr = fresh.ptr;
+        self.pc = 0;
+        self.stats = fresh.stats;
+        self.utf8_buf.clear();
+        self.journal.clear();
+        self.last_output = None;
+        self.rng = fresh.rng;
+        self.at_eof = false;
+        self.origin = 0;
+    }
+
+    /// Writes the tape, up to the highest cell touched, in a layout like
+    /// `hexdump -C`, with a sparse tape's unstored lines collapsed into `*`
+    pub fn dump_tape<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let cells = match &self.tape {
+            // The tape only grows when a cell past its end is touched
+            Tape::Dense(cells) => {
+                for (line, cells) in cells.chunks(16).enumerate() {
+                    self.dump_line(&mut out, line, cells)?;
+                }
+                return Ok(());
+            }
+            Tape::Sparse { cells, .. } => cells,
+        };
+        let lines: BTreeSet<usize> = cells.keys().map(|i| i / 16).collect();
+        let mut next = 0;
+        for line in lines {
+            if line > next {
+                writeln!(out, "*")?;
+            }
+            let end = self.tape.len().min(line * 16 + 16);
+            let cells: Vec<u32> = (line * 16..end).map(|i| self.tape.get(i)).collect();
+            self.dump_line(&mut out, line, &cells)?;
+            next = line + 1;
+        }
+        Ok(())
+    }
+
+    /// Writes one line of `dump_tape` holding up to 16 cells
+    fn dump_line<W: Write>(&self, out: &mut W, line: usize, cells: &[u32]) -> io::Result<()> {
+        let width = self.config.cell_size.bits() as usize / 4;
+        write!(out, "{:08x} ", line * 16)?;
+        for (i, cell) in cells.iter().enumerate() {
+            if i == 8 {
+                write!(out, " ")?;
+            }
+            write!(out, " {:0w$x}", cell, w = width)?;
+        }
+        for i in cells.len()..16 {
+            let pad = if i == 8 { 1 } else { 0 };
+            write!(out, "{:w$}", "", w = width + 1 + pad)?;
+        }
+        let ascii: String = cells
+            .iter()
+            .map(|&c| match c as u8 {
+                b if c == b as u32 && (b' '..=b'~').contains(&b) => b as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(out, "  |{}|", ascii)
+    }
+
+    /// Runs the passes the config asks for over freshly parsed instructions
+    fn optimize(&self, instrs: Vec<BfOp>, offsets: Vec<usize>) -> optimize::Mapped {
+        let mapped = optimize_mapped(instrs, offsets, self.config.opt_level);
+        match self.config.fold_io && self.config.opt_level != OptLevel::O0 {
+            true => optimize::fold_writes_offsets(mapped),
+            false => mapped,
+        }
+    }
+
+    /// Replaces the current program, keeping the tape and pointer
+    pub fn load(&mut self, code: impl AsRef<[u8]>) -> Result<(), ParseError> {
+        let (instrs, offsets) = parse_offsets(code.as_ref(), self.config.parse_options())?;
+        let (instrs, source_map) = self.optimize(instrs, offsets);
+        self.instrs = instrs;
+        self.source_map = source_map;
+        self.pc = 0;
+        Ok(())
+    }
+
+    /// Like `load`, parsing the source a chunk at a time from a reader
+    pub fn load_reader<R: Read>(&mut self, reader: R) -> Result<(), BfError> {
+        let (instrs, offsets) = parse_reader_offsets(reader, self.config.parse_options())?;
+        let (instrs, source_map) = self.optimize(instrs, offsets);
+        self.instrs = instrs;
+        self.source_map = source_map;
+        self.pc = 0;
+        Ok(())
+    }
+
+    /// Copies bytes into the tape starting at cell 0
+    pub fn init_tape(&mut self, data: &[u8]) -> Result<(), BfError> {
+        if self.config.tape_size.is_some_and(|n| data.len() > n) {
+            return Err(BfError::ConfigError(
+                "initial tape data is larger than the tape",
+            ));
+        }
+        self.tape.grow(data.len());
+        for (i, &byte) in data.iter().enumerate() {
+            self.tape[i] = byte as u32;
+        }
+        Ok(())
+    }
+
+    /// The low byte of each cell up to the highest one touched, which
+    /// `init_tape` reads back
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.init_tape(&[5, 1]).unwrap();
+    /// interp.eval("+>>>+", io::empty(), io::sink()).unwrap();
+    /// assert_eq!(interp.tape_bytes(), [6, 1, 0, 1]);
+    /// ```
+    pub fn tape_bytes(&self) -> Vec<u8> {
+        (0..self.tape.len())
+            .map(|i| self.tape.get(i) as u8)
+            .collect()
+    }
+
+    /// Makes `run_stepping` pause before executing the instruction at `pc`
+    ///
+    /// ```
+    /// use brainfuck::{parse, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.load_ops(parse(b"+++.").unwrap());
+    /// interp.add_breakpoint(2);
+    /// let mut trace = Vec::new();
+    /// interp
+    ///     .run_stepping(io::empty(), io::sink(), &b"c\n"[..], &mut trace)
+    ///     .unwrap();
+    /// assert_eq!(trace, b"pc=2 Plus ptr=0 cell=2 ");
+    /// ```
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Stops the program with `BfError::Interrupted` before the next
+    /// instruction once `flag` is set, leaving the tape and stats as they
+    /// were so they can be reported
+    ///
+    /// ```
+    /// use brainfuck::{BfError, Config, Interpreter};
+    /// use std::io;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    ///
+    /// static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.set_interrupt(&INTERRUPTED);
+    /// interp.load("+[>+<]").unwrap();
+    /// interp.run_for(10, io::empty(), io::sink()).unwrap();
+    /// INTERRUPTED.store(true, Ordering::Relaxed);
+    /// let err = interp.run(io::empty(), io::sink()).unwrap_err();
+    /// assert!(matches!(err, BfError::Interrupted { .. }));
+    /// assert_eq!(interp.stats().steps, 10);
+    /// assert!(interp.cell_at(1) > 0);
+    /// ```
+    pub fn set_interrupt(&mut self, flag: &'static AtomicBool) {
+        self.interrupt = Some(flag);
+    }
+
+    /// Replaces the current program with instructions whose bracket offsets
+    /// are already resolved, keeping the tape and pointer
+    pub fn load_ops(&mut self, instrs: Vec<BfOp>) {
+        self.instrs = instrs;
+        self.source_map.clear();
+        self.pc = 0;
+    }
+
+    /// The source byte offset of the instruction at `pc`, if the program was
+    /// loaded from source
+    ///
+    /// Optimized instructions map to the first command of the loop they
+    /// replace or the last command of the run they fold.
+    pub fn source_offset(&self, pc: usize) -> Option<usize> {
+        self.source_map.get(pc).copied()
+    }
+
+    /// Shows where in `code`, the loaded source, a runtime error happened
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let code = "+>>\n  <<<<.";
+    /// let mut interp = Interpreter::new(Config::default());
+    /// let err = interp.eval(code, io::empty(), io::sink()).unwrap_err();
+    /// assert_eq!(
+    ///     interp.error_context(&err, code.as_bytes()).unwrap(),
+    ///     "line 2, column 6:\n      <<<<.\n         ^"
+    /// );
+    /// ```
+    pub fn error_context(&self, err: &BfError, code: &[u8]) -> Option<String> {
+        let offset = self.source_offset(err.pc()?)?;
+        Some(snippet(code, offset))
+    }
+
+    /// Loads and runs a program
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let config = Config {
+    ///     extensions: true,
+    ///     start_offset: 2,
+    ///     ..Config::default()
+    /// };
+    /// let mut interp = Interpreter::new(config);
+    /// interp.eval("+>++>+++<<<+~", io::empty(), io::sink()).unwrap();
+    /// assert!(interp.tape().iter().all(|&c| c == 0));
+    /// assert_eq!(interp.ptr(), 2);
+    /// ```
+    pub fn eval<R: Read, W: Write>(
+        &mut self,
+        code: impl AsRef<[u8]>,
+        input: R,
+        output: W,
+    ) -> Result<RunStats, BfError> {
+        self.load(code)?;
+        self.run(input, output)
+    }
+
+    /// Runs the loaded program from the current instruction to the end,
+    /// buffering its output according to the config, and returns counters
+    /// for this run
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter};
+    /// use std::io::{self, Write};
+    ///
+    /// /// Counts the calls to `write`
+    /// struct Counter(usize);
+    ///
+    /// impl Write for Counter {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         self.0 += 1;
+    ///         Ok(buf.len())
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// // Print 1000 As, stopped by the output limit
+    /// let program = "++++++++[>++++++++<-]>+[.]";
+    /// let mut writes = Vec::new();
+    /// for output_buffer in vec![0, 64] {
+    ///     let mut counter = Counter(0);
+    ///     let config = Config {
+    ///         output_buffer,
+    ///         max_output: 1000,
+    ///         ..Config::default()
+    ///     };
+    ///     Interpreter::new(config)
+    ///         .eval(program, io::empty(), &mut counter)
+    ///         .unwrap_err();
+    ///     writes.push(counter.0);
+    /// }
+    /// assert_eq!(writes, vec![1000, 16]);
+    /// ```
+    pub fn run<R: Read, W: Write>(&mut self, mut input: R, output: W) -> Result<RunStats, BfError> {
+        self.begin()?;
+        let stats = &self.stats;
+        let before = (stats.steps, stats.output_bytes, stats.input_bytes);
+        self.max_ptr = self.ptr;
+        // Dropping the buffer on an error writes what it holds
+        let mut output = io::BufWriter::with_capacity(self.config.output_buffer, output);
+        while self.pc < self.instrs.len() {
+            self.step(&mut input, &mut output)?;
+        }
+        self.finish(&mut output)?;
+        output.flush()?;
+        Ok(RunStats {
+            steps: self.stats.steps - before.0,
+            bytes_written: self.stats.output_bytes - before.1,
+            bytes_read: self.stats.input_bytes - before.2,
+            max_ptr: self.max_ptr,
+        })
+    }
+
+    /// Runs the loaded program, printing the state to `trace` before each
+    /// instruction and waiting for a line from `control`; a line of `c`
+    /// continues to completion or the next breakpoint
+    ///
+    /// If any breakpoints are set, the program runs until it reaches one
+    /// before it starts stepping.
+    pub fn run_stepping<R: Read, W: Write, C: BufRead, T: Write>(
+        &mut self,
+        mut input: R,
+        mut output: W,
+        mut control: C,
+        mut trace: T,
+    ) -> Result<(), BfError> {
+        self.begin()?;
+        let mut stepping = self.breakpoints.is_empty();
+        while self.pc < self.instrs.len() {
+            if self.breakpoints.contains(&self.pc) {
+                stepping = true;
+            }
+            if stepping {
+                write!(
+                    trace,
+                    "pc={} {:?} ptr={} cell={} ",
+                    self.pc,
+                    self.instrs[self.pc],
+                    self.ptr,
+                    self.cell()
+                )?;
+                trace.flush()?;
+                let mut line = String::new();
+                if control.read_line(&mut line)? == 0 {
+                    writeln!(trace)?;
+                    stepping = false;
+                } else if line.trim() == "c" {
+                    stepping = false;
+                } else if line.trim() == "b" {
+                    if !self.step_back() {
+                        writeln!(trace, "nothing to step back to")?;
+                    }
+                    continue;
+                }
+            }
+            self.step(&mut input, &mut output)?;
+        }
+        self.finish(&mut output)
+    }
+
+    /// Executes at most `steps` instructions, returning whether the program
+    /// has finished; a later call continues where this one stopped, so
+    /// several programs can take turns
+    ///
+    /// ```
+    /// use brainfuck::{Config, Interpreter, RunState};
+    ///
+    /// let code = "+++++[>++++++++++<-]>.+.+.";
+    /// let mut whole = Vec::new();
+    /// Interpreter::new(Config::default())
+    ///     .eval(code, &b""[..], &mut whole)
+    ///     .unwrap();
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.load(code).unwrap();
+    /// let mut sliced = Vec::new();
+    /// let first = interp.run_for(5, &b""[..], &mut sliced).unwrap();
+    /// assert_eq!((first, &sliced[..]), (RunState::Running, &b"2"[..]));
+    /// let second = interp.run_for(usize::MAX, &b""[..], &mut sliced).unwrap();
+    /// assert_eq!(second, RunState::Finished);
+    /// assert_eq!(sliced, whole);
+    /// ```
+    pub fn run_for<R: Read, W: Write>(
+        &mut self,
+        steps: usize,
+        mut input: R,
+        mut output: W,
+    ) -> Result<RunState, BfError> {
+        self.begin()?;
+        for _ in 0..steps {
+            if self.pc >= self.instrs.len() {
+                break;
+            }
+            self.step(&mut input, &mut output)?;
+        }
+        let state = match self.pc < self.instrs.len() {
+            true => RunState::Running,
+            false => {
+                self.finish(&mut output)?;
+                RunState::Finished
+            }
+        };
+        output.flush()?;
+        Ok(state)
+    }
+
+    /// Executes a single instruction, returning false if the program has
+    /// already finished
+    pub fn step_forward<R: Read, W: Write>(
+        &mut self,
+        mut input: R,
+        mut output: W,
+    ) -> Result<bool, BfError> {
+        self.begin()?;
+        if self.pc >= self.instrs.len() {
+            return Ok(false);
+        }
+        self.step(&mut input, &mut output)?;
+        Ok(true)
+    }
+
+    /// Returns an iterator that executes one instruction per item, yielding
+    /// the state it ran in, until the program finishes or fails
+    ///
+    /// ```
+    /// use brainfuck::{parse, BfOp, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.load_ops(parse(b"+>++<.").unwrap());
+    /// let mut out = Vec::new();
+    /// let steps: Vec<_> = interp
+    ///     .steps(io::empty(), &mut out)
+    ///     .take(5)
+    ///     .map(|step| step.unwrap())
+    ///     .map(|step| (step.pc, step.ptr, step.op, step.cell))
+    ///     .collect();
+    /// assert_eq!(
+    ///     steps,
+    ///     vec![
+    ///         (0, 0, BfOp::Plus, 0),
+    ///         (1, 0, BfOp::Gt, 1),
+    ///         (2, 1, BfOp::Plus, 0),
+    ///         (3, 1, BfOp::Plus, 1),
+    ///         (4, 1, BfOp::Lt, 2),
+    ///     ]
+    /// );
+    /// assert!(out.is_empty());
+    /// ```
+    pub fn steps<R: Read, W: Write>(&mut self, input: R, output: W) -> Steps<'_, R, W> {
+        Steps {
+            interp: self,
+            input,
+            output,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Runs the loaded program like `run`, writing a line to `trace` for each
+    /// instruction executed with its `pc`, the pointer, the current cell
+    /// before it ran and its name, separated by tabs
+    ///
+    /// ```
+    /// use brainfuck::{parse, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let mut interp = Interpreter::new(Config::default());
+    /// interp.load_ops(parse(b"+[-]>").unwrap());
+    /// let mut trace = Vec::new();
+    /// interp.run_traced(io::empty(), io::sink(), &mut trace).unwrap();
+    /// let trace = String::from_utf8(trace).unwrap();
+    /// assert_eq!(trace.lines().count(), 5);
+    /// assert_eq!(trace.lines().nth(1), Some("1\t0\t1\tLBracket"));
+    /// ```
+    pub fn run_traced<R: Read, W: Write, T: Write>(
+        &mut self,
+        input: R,
+        output: W,
+        trace: T,
+    ) -> Result<(), BfError> {
+        let mut trace = io::BufWriter::new(trace);
+        let mut output = io::BufWriter::with_capacity(self.config.output_buffer, output);
+        for step in self.steps(input, &mut output) {
+            let step = step?;
+            writeln!(
+                trace,
+                "{}\t{}\t{}\t{}",
+                step.pc,
+                step.ptr,
+                step.cell,
+                step.op.name()
+            )?;
+        }
+        output.flush()?;
+        Ok(trace.flush()?)
+    }
+
+    /// Undoes the last recorded instruction, restoring `pc`, the pointer and
+    /// the cell it changed; returns false if there is nothing to undo
+    ///
+    /// Only instructions run with `record` enabled can be undone, and output
+    /// already written is not taken back.
+    pub fn step_back(&mut self) -> bool {
+        match self.journal.pop() {
+            Some(entry) => {
+                self.pc = entry.pc;
+                self.ptr = entry.ptr;
+                if let Some((i, value)) = entry.cell {
+                    self.tape[i] = value;
+                }
+                if let Some(tape) = entry.tape {
+                    self.tape = tape;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Saves the state the instruction at `pc` may change to the journal
+    fn record(&mut self) {
+        let ptr = self.ptr;
+        let index = match self.instrs[self.pc] {
+            Plus | Minus | Add(_) | Clear | SetValue(_) | Comma | Random => Some(ptr),
+            MulAdd { offset, .. } | AddAt { offset, .. } => {
+                let i = (ptr as isize).checked_add(offset);
+                match (self.config.tape_size, i) {
+                    (Some(n), Some(i)) if self.config.wrap_pointer => {
+                        Some(i.rem_euclid(n as isize) as usize)
+                    }
+                    (_, Some(i)) if i >= 0 => Some(i as usize),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        // Cells past the end of the tape are zero until it grows
+        let cell = index.map(|i| (i, self.tape.get(i)));
+        let tape = match self.instrs[self.pc] {
+            Reset | ClearRange { .. } => Some(self.tape.clone()),
+            _ => None,
+        };
+        self.journal.push(JournalEntry {
+            pc: self.pc,
+            ptr,
+            cell,
+            tape,
+        });
+    }
+
+    /// Writes any incomplete UTF-8 sequence left at the end of a run
+    fn finish<W: Write>(&mut self, output: &mut W) -> Result<(), BfError> {
+        // Bytes written here, which aren't counted as program output
+        let mut extra = 0;
+        if !self.utf8_buf.is_empty() {
+            self.utf8_buf.clear();
+            write!(output, "{}", char::REPLACEMENT_CHARACTER)?;
+            self.last_output = Some(b'?');
+            extra += char::REPLACEMENT_CHARACTER.len_utf8() as u64;
+        }
+        if self.config.newline_at_end && self.last_output.is_some_and(|b| b != b'\n') {
+            output.write_all(b"\n")?;
+            self.last_output = Some(b'\n');
+            extra += 1;
+        }
+        if let Some(len) = self.config.pad_output {
+            let written = self.stats.output_bytes + extra;
+            if written > len {
+                return Err(BfError::OutputTooLong { len, written });
+            }
+            io::copy(&mut io::repeat(0).take(len - written), output)?;
+        }
+        Ok(())
+    }
+
+    /// Buffers an output byte until it completes a UTF-8 sequence, writing
+    /// invalid sequences as U+FFFD
+    fn write_utf8_byte<W: Write>(&mut self, byte: u8, output: &mut W) -> io::Result<()> {
+        self.utf8_buf.push(byte);
+        while !self.utf8_buf.is_empty() {
+            match std::str::from_utf8(&self.utf8_buf) {
+                Ok(s) => {
+                    output.write_all(s.as_bytes())?;
+                    self.utf8_buf.clear();
+                }
+                Err(e) => match e.error_len() {
+                    Some(len) => {
+                        write!(output, "{}", char::REPLACEMENT_CHARACTER)?;
+                        self.utf8_buf.drain(..len);
+                    }
+                    None => break,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats a cell for `.` other than in the buffered UTF-8 mode,
+    /// returning the length written to `buf`
+    fn format_cell(&self, value: u32, buf: &mut [u8]) -> usize {
+        let mut buf = io::Cursor::new(buf);
+        let result = if self.config.numeric && self.config.signed {
+            write!(buf, "{} ", self.config.cell_size.to_signed(value))
+        } else if self.config.numeric {
+            write!(buf, "{} ", value)
+        } else if self.config.utf8 {
+            // Wide cells hold a code point
+            let c = std::char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER);
+            write!(buf, "{}", c)
+        } else {
+            let byte = self.config.output_map[value as u8 as usize];
+            match self.config.escape_output && !is_printable(byte) {
+                true => write!(buf, "\\x{:02x}", byte),
+                false => buf.write_all(&[byte]),
+            }
+        };
+        result.expect("formatted cell is too long");
+        buf.position() as usize
+    }
+
+    /// Counts `len` bytes of output, failing instead if that would exceed
+    /// the limit
+    fn count_output(&mut self, len: usize) -> Result<(), BfError> {
+        let total = self.stats.output_bytes + len as u64;
+        let limit = self.config.max_output;
+        if limit != 0 && total > limit {
+            return Err(BfError::OutputLimitExceeded { limit });
+        }
+        self.stats.output_bytes = total;
+        Ok(())
+    }
+
+    /// Checks the config and starts the clock for `timeout` before running
+    fn begin(&mut self) -> Result<(), BfError> {
+        self.check_config()?;
+        self.deadline = self.config.timeout.map(|t| Instant::now() + t);
+        Ok(())
+    }
+
+    fn check_config(&self) -> Result<(), BfError> {
+        if self.config.tape_size == Some(0) {
+            return Err(BfError::ConfigError("tape size must be at least 1"));
+        }
+        if self
+            .config
+            .tape_size
+            .is_some_and(|n| self.config.start_offset >= n)
+        {
+            return Err(BfError::ConfigError("start offset must be within the tape"));
+        }
+        if self.config.wrap_pointer && self.config.tape_size.is_none() {
+            return Err(BfError::ConfigError(
+                "wrapping the pointer requires a tape size",
+            ));
+        }
+        if self.config.bidirectional && self.config.tape_size.is_some() {
+            return Err(BfError::ConfigError(
+                "a bidirectional tape can't have a size",
+            ));
+        }
+        if self.config.bidirectional && self.config.record {
+            return Err(BfError::ConfigError(
+                "recording doesn't support bidirectional tapes",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Maps a cell position to a tape index according to the configured
+    /// bounds, growing the tape if needed
+    fn resolve(&mut self, mut position: isize) -> Result<usize, BfError> {
+        let limit = self.config.tape_size.unwrap_or(usize::MAX);
+        if position < 0 && self.config.bidirectional {
+            // Growing by at least the current length keeps moving left
+            // cheap on average
+            let extra = position.unsigned_abs().max(self.tape.len());
+            self.tape.grow_front(extra);
+            self.origin += extra;
+            self.ptr += extra;
+            self.max_ptr += extra;
+            position += extra as isize;
+        }
+        if self.config.wrap_pointer {
+            position = position.rem_euclid(limit as isize);
+        } else if position < 0 || position as usize >= limit {
+            return Err(BfError::PointerOutOfBounds {
+                position,
+                pc: self.pc,
+            });
+        }
+        let position = position as usize;
+        self.tape.grow(position + 1);
+        Ok(position)
+    }
+
+    /// The position `offset` cells from `base`, which is out of bounds if
+    /// it doesn't fit in an `isize`
+    fn offset(&self, base: isize, offset: isize) -> Result<isize, BfError> {
+        base.checked_add(offset).ok_or(BfError::PointerOutOfBounds {
+            position: base.saturating_add(offset),
+            pc: self.pc,
+        })
+    }
+
+    /// Moves the pointer to `position`
+    fn move_to(&mut self, position: isize) -> Result<(), BfError> {
+        self.ptr = self.resolve(position)?;
+        self.max_ptr = self.max_ptr.max(self.ptr);
+        Ok(())
+    }
+
+    /// Moves the pointer one cell to the right
+    fn move_right(&mut self) -> Result<(), BfError> {
+        self.move_to(self.ptr as isize + 1)
+    }
+
+    /// Moves the pointer one cell to the left
+    fn move_left(&mut self) -> Result<(), BfError> {
+        self.move_to(self.ptr as isize - 1)
+    }
+
+    /// Writes the current cell `count` times, as that many `.` would
+    fn write_cell<W: Write>(&mut self, output: &mut W, count: usize) -> Result<(), BfError> {
+        let value = match self.config.ascii7 {
+            Some(Ascii7::Mask) => self.cell() & 0x7f,
+            Some(Ascii7::Reject) if self.cell() > 0x7f => {
+                return Err(BfError::NonAsciiOutput {
+                    value: self.cell(),
+                    pc: self.pc,
+                });
+            }
+            _ => self.cell(),
+        };
+        if self.config.utf8 && self.config.cell_size == CellSize::U8 {
+            // Bytes are buffered until they form a whole character
+            for _ in 0..count {
+                self.count_output(1)?;
+                self.write_utf8_byte(value as u8, output)?;
+            }
+            self.last_output = Some(value as u8);
+        } else if count > 0 {
+            let mut buf = [0; 16];
+            let len = self.format_cell(value, &mut buf);
+            // Write as many copies as the output limit allows before failing
+            let fits = match self.config.max_output {
+                0 => count,
+                limit => ((limit.saturating_sub(self.stats.output_bytes)) / len as u64)
+                    .min(count as u64) as usize,
+            };
+            let chunk = buf[..len].repeat((REPEAT_CHUNK / len).min(fits));
+            let mut left = fits;
+            while left > 0 {
+                let n = left.min(chunk.len() / len);
+                output.write_all(&chunk[..n * len])?;
+                self.count_output(n * len)?;
+                left -= n;
+            }
+            if fits < count {
+                self.count_output(len)?;
+            }
+            self.last_output = buf[..len].last().copied();
+        }
+        if self.config.unbuffered {
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The value of the current cell
+    fn cell(&self) -> u32 {
+        self.tape[self.ptr]
+    }
+
+    /// The current cell, which `move_to` keeps within the tape
+    fn cell_mut(&mut self) -> &mut u32 {
+        &mut self.tape[self.ptr]
+    }
+
+    /// Moves the pointer to the nearest zero cell in the direction of
+    /// `right`, returning false if every cell of a wrapping tape is nonzero
+    fn scan(&mut self, right: bool) -> Result<bool, BfError> {
+        if let Tape::Sparse { .. } = self.tape {
+            return self.scan_sparse(right);
+        }
+        let ptr = self.ptr;
+        let full = self.config.tape_size == Some(self.tape.len());
+        let tape = self.tape.as_slice();
+        let found = if right {
+            match tape[ptr..].iter().position(|&c| c == 0) {
+                Some(i) => Some(ptr + i),
+                // Cells past the end of a growable tape are implicitly zero
+                None if !full => Some(tape.len()),
+                None if self.config.wrap_pointer => tape[..ptr].iter().position(|&c| c == 0),
+                None => Some(tape.len()),
+            }
+            .map(|i| i as isize)
+        } else {
+            match tape[..=ptr].iter().rposition(|&c| c == 0) {
+                Some(i) => Some(i as isize),
+                None if !self.config.wrap_pointer => Some(-1),
+                None if !full => self.config.tape_size.map(|n| n as isize - 1),
+                None => tape[ptr..]
+                    .iter()
+                    .rposition(|&c| c == 0)
+                    .map(|i| (ptr + i) as isize),
+            }
+        };
+        match found {
+            Some(position) => self.move_to(position).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Scans a sparse tape a cell at a time, since most of it isn't stored
+    fn scan_sparse(&mut self, right: bool) -> Result<bool, BfError> {
+        // A wrapping tape with every cell nonzero would loop forever
+        let mut remaining = self.config.tape_size.unwrap_or(usize::MAX);
+        while self.cell() != 0 {
+            if remaining == 0 {
+                return Ok(false);
+            }
+            remaining -= 1;
+            match right {
+                true => self.move_right()?,
+                false => self.move_left()?,
+            }
+        }
+        Ok(true)
+    }
+
+    /// Executes the instruction at `pc`, reporting changes to watched cells
+    fn step<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<(), BfError> {
+        if self.config.record {
+            self.record();
+        }
+        if self.config.watch.is_empty() || self.config.quiet {
+            let pc = self.pc;
+            return self.exec(input, output).map_err(|e| e.at(pc));
+        }
+        let before: Vec<_> = self
+            .config
+            .watch
+            .iter()
+            .map(|&i| self.tape.get(self.origin + i))
+            .collect();
+        let pc = self.pc;
+        self.exec(input, output).map_err(|e| e.at(pc))?;
+        for (&i, &old) in self.config.watch.iter().zip(&before) {
+            let new = self.tape.get(self.origin + i);
+            if new != old {
+                eprintln!(
+                    "watch: cell {} changed from {} to {} at instruction {}",
+                    i, old, new, pc
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the instruction at `pc`
+    fn exec<R: Read, W: Write>(&mut self, input: &mut R, output: &mut W) -> Result<(), BfError> {
+        let (pc, ptr) = (self.pc, self.ptr);
+        if self
+            .interrupt
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+        {
+            return Err(BfError::Interrupted { pc });
+        }
+        if self.config.max_steps != 0 && self.stats.steps >= self.config.max_steps {
+            return Err(BfError::StepLimitExceeded {
+                limit: self.config.max_steps,
+            });
+        }
+        self.stats.steps += 1;
+        if let Some(deadline) = self.deadline {
+            // Reading the clock every instruction would be slow
+            if self.stats.steps.is_multiple_of(TIMEOUT_INTERVAL) && Instant::now() >= deadline {
+                let limit = self.config.timeout.unwrap_or_default();
+                return Err(BfError::Timeout { limit });
+            }
+        }
+        if self.config.stats {
+            *self.stats.ops.entry(self.instrs[pc].name()).or_insert(0) += 1;
+        }
+        let mask = self.config.cell_size.mask();
+        match self.instrs[pc] {
+            Gt => self.move_right()?,
+            Lt => self.move_left()?,
+            Move(n) => self.move_to(self.offset(ptr as isize, n)?)?,
+            Plus => *self.cell_mut() = self.cell().wrapping_add(1) & mask,
+            Minus => *self.cell_mut() = self.cell().wrapping_sub(1) & mask,
+            Add(n) => *self.cell_mut() = self.cell().wrapping_add(n as u32) & mask,
+            Clear => *self.cell_mut() = 0,
+            SetValue(n) => *self.cell_mut() = n & mask,
+            Dot => self.write_cell(output, 1)?,
+            WriteRepeat { count } => self.write_cell(output, count)?,
+            Comma => {
+                if let Some(limit) = self.config.expect_input {
+                    if self.stats.input_bytes >= limit {
+                        return Err(BfError::UnexpectedInputRead { limit, pc });
+                    }
+                }
+                // Show any prompt before waiting for input
+                output.flush()?;
+                let eof = match self.config.eof {
+                    Eof::Zero => 0,
+                    Eof::MinusOne => mask,
+                    Eof::Unchanged => self.cell(),
+                };
+                let mut input = CountingReader {
+                    inner: input,
+                    count: 0,
+                };
+                let input = &mut input;
+                let value = if self.at_eof {
+                    None
+                } else if self.config.numeric {
+                    read_number(input, self.config.signed)?.map(|n| n & mask)
+                } else if self.config.utf8 && self.config.cell_size != CellSize::U8 {
+                    // Wide cells receive a whole code point, or U+FFFD if it
+                    // doesn't fit; 8-bit cells read the encoded bytes one at
+                    // a time below
+                    read_char(input)?.map(|c| match c as u32 {
+                        c if c <= mask => c,
+                        _ => char::REPLACEMENT_CHARACTER as u32,
+                    })
+                } else {
+                    read_byte(input)?.map(|byte| self.config.input_map[byte as usize] as u32)
+                };
+                self.at_eof = self.config.sticky_eof && value.is_none();
+                self.stats.input_bytes += input.count;
+                *self.cell_mut() = value.unwrap_or(eof);
+            }
+            Random => {
+                let byte = random_byte(&mut self.rng);
+                *self.cell_mut() = byte as u32;
+            }
+            Reset => {
+                self.tape.clear();
+                self.ptr = self.origin + self.config.start_offset;
+            }
+            Comment(_) => {}
+            Debug if self.config.quiet => {}
+            Debug => eprintln!("{}", debug_window(self.tape.len(), ptr, |i| self.tape[i])),
+            ScanRight | ScanLeft => {
+                if !self.scan(matches!(self.instrs[pc], ScanRight))? {
+                    // Spin like the unoptimized loop would
+                    return Ok(());
+                }
+            }
+            MulAdd { offset, factor } => {
+                let value = self.cell();
+                if value != 0 {
+                    let i = self.resolve(self.offset(ptr as isize, offset)?)?;
+                    let product = match factor {
+                        1 => value,
+                        _ => value.wrapping_mul(factor as u32),
+                    };
+                    self.tape[i] = self.tape[i].wrapping_add(product) & mask;
+                }
+            }
+            AddAt { offset, val } => {
+                let i = self.resolve(self.offset(ptr as isize, offset)?)?;
+                self.tape[i] = self.tape[i].wrapping_add(val as u32) & mask;
+            }
+            ClearRange { from, len } => {
+                let start = self.offset(ptr as isize, from)?;
+                if self.config.wrap_pointer {
+                    // The range may wrap around the end of the tape
+                    for k in 0..len as isize {
+                        let i = self.resolve(self.offset(start, k)?)?;
+                        self.tape[i] = 0;
+                    }
+                } else if len > 0 {
+                    // Resolving the start may grow a bidirectional tape to
+                    // the left, moving the pointer
+                    let first = self.resolve(start)?;
+                    let last = self.offset(self.ptr as isize, from)?;
+                    let last = self.resolve(self.offset(last, len as isize - 1)?)?;
+                    self.tape.clear_range(first..last + 1);
+                }
+            }
+            PrintInt => {
+                let text = self.cell().to_string();
+                self.count_output(text.len())?;
+                output.write_all(text.as_bytes())?;
+                self.last_output = text.bytes().last();
+                if self.config.unbuffered {
+                    output.flush()?;
+                }
+            }
+            Halt => {
+                self.pc = self.instrs.len();
+                return Ok(());
+            }
+            LBracket(i) => {
+                if self.cell() == 0 {
+                    self.pc = i
+                }
+            }
+            LoopStart(_) => {}
+            RBracket(i) => {
+                if self.cell() != 0 {
+                    self.stats.loop_iterations += 1;
+                    if self.config.profile {
+                        *self.stats.loops.entry(i).or_insert(0) += 1;
+                    }
+                    self.pc = i
+                }
+            }
+        }
+        self.pc += 1;
+        Ok(())
+    }
+}
+
+/// An iterator over the instructions executed by an interpreter, created by
+/// `Interpreter::steps`
+pub struct Steps<'a, R, W> {
+    interp: &'a mut Interpreter,
+    input: R,
+    output: W,
+    started: bool,
+    done: bool,
+}
+
+impl<R: Read, W: Write> Steps<'_, R, W> {
+    /// The interpreter, in its state before the next instruction
+    pub fn interpreter(&self) -> &Interpreter {
+        self.interp
+    }
+
+    /// The writer the program's output goes to
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    fn next_step(&mut self) -> Result<Option<StepInfo>, BfError> {
+        let interp = &mut *self.interp;
+        if !self.started {
+            self.started = true;
+            interp.begin()?;
+        }
+        if interp.pc >= interp.instrs.len() {
+            self.done = true;
+            interp.finish(&mut self.output)?;
+            return Ok(None);
+        }
+        let info = StepInfo {
+            pc: interp.pc,
+            ptr: interp.ptr,
+            op: interp.instrs[interp.pc].clone(),
+            cell: interp.cell(),
+        };
+        interp.step(&mut self.input, &mut self.output)?;
+        Ok(Some(info))
+    }
+}
+
+impl<R: Read, W: Write> Iterator for Steps<'_, R, W> {
+    type Item = Result<StepInfo, BfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let step = self.next_step();
+        if step.is_err() {
+            self.done = true;
+        }
+        step.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer that takes `room` bytes and then reports that it wrote
+    /// nothing, like a full disk
+    struct Full {
+        room: usize,
+        written: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.room - self.written);
+            self.written += n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn huge_write_repeat_is_written_in_chunks() {
+        let ops = vec![Plus, WriteRepeat { count: 1 << 62 }];
+        let mut interp = Interpreter::from_ops(Config::default(), ops).unwrap();
+        let mut out = Full {
+            room: 1 << 20,
+            written: 0,
+        };
+        let err = interp.run(io::empty(), &mut out).unwrap_err();
+        assert!(matches!(err, BfError::IoError { ref source, pc: 1 }
+            if source.kind() == io::ErrorKind::WriteZero));
+        assert_eq!(out.written, 1 << 20);
+    }
+
+    #[test]
+    fn random_cells_repeat_with_the_same_seed() {
+        let random_cells = |seed| {
+            let config = Config {
+                extensions: true,
+                seed,
+                ..Config::default()
+            };
+            let mut interp = Interpreter::new(config);
+            interp.eval("?>?>?>?", io::empty(), io::sink()).unwrap();
+            interp.tape().to_vec()
+        };
+        assert_eq!(random_cells(7), random_cells(7));
+        assert_ne!(random_cells(7), random_cells(8));
+    }
+
+    #[test]
+    fn moving_past_the_tape_end_is_an_error() {
+        let code = ">".repeat(65536);
+        let config = Config {
+            tape_size: Some(65536),
+            ..Config::default()
+        };
+        let mut interp = Interpreter::new(config.clone());
+        let result = interp.eval(&code, io::empty(), io::sink());
+        assert!(matches!(
+            result,
+            Err(BfError::PointerOutOfBounds {
+                position: 65536,
+                pc: 0
+            })
+        ));
+        assert_eq!(interp.ptr(), 0);
+
+        let mut interp = Interpreter::new(Config {
+            opt_level: OptLevel::O0,
+            ..config
+        });
+        let result = interp.eval(&code, io::empty(), io::sink());
+        assert!(matches!(
+            result,
+            Err(BfError::PointerOutOfBounds {
+                position: 65536,
+                pc: 65535
+            })
+        ));
+        assert_eq!(interp.ptr(), 65535);
+    }
+
+    #[test]
+    fn moving_left_of_cell_0_is_an_error() {
+        let result = Interpreter::new(Config::default()).eval("<", io::empty(), io::sink());
+        assert!(matches!(
+            result,
+            Err(BfError::PointerOutOfBounds {
+                position: -1,
+                pc: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn writer_taking_nothing_is_an_error() {
+        for output_buffer in [0, 8192] {
+            let config = Config {
+                output_buffer,
+                ..Config::default()
+            };
+            let mut out = Full {
+                room: 0,
+                written: 0,
+            };
+            let result = Interpreter::new(config).eval("+.", io::empty(), &mut out);
+            match result {
+                Err(BfError::IoError { source, .. }) | Err(BfError::RuntimeError(source)) => {
+                    assert_eq!(source.kind(), io::ErrorKind::WriteZero)
+                }
+                result => panic!("expected a write error, got {:?}", result),
+            }
+        }
+    }
+
+    /// The current cell after `+++,` reads from empty input
+    fn cell_after_eof(eof: Eof) -> u32 {
+        let mut interp = Interpreter::new(Config {
+            eof,
+            ..Config::default()
+        });
+        interp.eval("+++,", io::empty(), io::sink()).unwrap();
+        interp.cell_at(0)
+    }
+
+    #[test]
+    fn eof_stores_zero() {
+        assert_eq!(cell_after_eof(Eof::Zero), 0);
+    }
+
+    #[test]
+    fn eof_stores_minus_one() {
+        assert_eq!(cell_after_eof(Eof::MinusOne), 255);
+    }
+
+    #[test]
+    fn eof_leaves_the_cell_unchanged() {
+        assert_eq!(cell_after_eof(Eof::Unchanged), 3);
+    }
+
+    #[test]
+    fn stray_bracket_reports_its_line_and_column() {
+        let err = parse(b"+[-]\n++ comment\n+ -]+").unwrap_err();
+        assert!(matches!(err, UnmatchedRightBracket { line: 3, col: 4 }));
+        assert_eq!(
+            err.to_string(),
+            "Unmatched right bracket at line 3, column 4"
+        );
+    }
+
+    #[test]
+    fn stepper_traces_each_instruction() {
+        let mut interp = Interpreter::new(Config {
+            opt_level: OptLevel::O0,
+            ..Config::default()
+        });
+        interp.load("+>+").unwrap();
+        let mut trace = Vec::new();
+        let control = &b"\n\nc\n"[..];
+        interp
+            .run_stepping(io::empty(), io::sink(), control, &mut trace)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(trace).unwrap(),
+            "pc=0 Plus ptr=0 cell=0 pc=1 Gt ptr=0 cell=1 pc=2 Plus ptr=1 cell=0 "
+        );
+        assert_eq!(interp.tape(), [1, 1]);
+    }
+
+    #[test]
+    fn numeric_input_reads_a_number() {
+        let config = Config {
+            numeric: true,
+            ..Config::default()
+        };
+        let mut interp = Interpreter::new(config.clone());
+        let mut out = Vec::new();
+        interp.eval(",.", &b"42"[..], &mut out).unwrap();
+        assert_eq!(interp.cell_at(0), 42);
+        assert!(String::from_utf8(out).unwrap().starts_with("42"));
+
+        let mut interp = Interpreter::new(config.clone());
+        interp.eval(",", &b"300"[..], io::sink()).unwrap();
+        assert_eq!(interp.cell_at(0), 300 % 256);
+
+        let result = Interpreter::new(config).eval(",", &b"abc"[..], io::sink());
+        assert!(matches!(result, Err(BfError::InvalidNumber(_))));
+    }
+
+    #[test]
+    fn step_count_matches_a_hand_count() {
+        let mut interp = Interpreter::new(Config {
+            opt_level: OptLevel::O0,
+            ..Config::default()
+        });
+        // + + [ - ] - ], with each ] jumping back past its [
+        let stats = interp.eval("++[-]", io::empty(), io::sink()).unwrap();
+        assert_eq!(stats.steps, 7);
+        assert_eq!(interp.stats().steps, 7);
+        assert_eq!(interp.stats().loop_iterations, 1);
+    }
+
+    #[test]
+    fn endless_loop_hits_the_step_limit() {
+        let config = Config {
+            max_steps: 1000,
+            ..Config::default()
+        };
+        let mut interp = Interpreter::new(config);
+        let result = interp.eval("+[]", io::empty(), io::sink());
+        assert!(matches!(
+            result,
+            Err(BfError::StepLimitExceeded { limit: 1000 })
+        ));
+        assert_eq!(interp.stats().steps, 1000);
+    }
+
+    #[test]
+    fn tape_grows_to_cell_100000_and_back() {
+        let code = format!("{}+{}++", ">".repeat(100000), "<".repeat(100000));
+        for &opt_level in &[OptLevel::O0, OptLevel::O2] {
+            let mut interp = Interpreter::new(Config {
+                opt_level,
+                ..Config::default()
+            });
+            interp.eval(&code, io::empty(), io::sink()).unwrap();
+            assert_eq!(interp.tape().len(), 100001);
+            assert_eq!((interp.cell_at(0), interp.cell_at(100000)), (2, 1));
+            assert_eq!(interp.ptr(), 0);
+        }
+    }
+
+    #[test]
+    fn pointer_wraps_around_a_tape_of_4() {
+        let config = Config {
+            tape_size: Some(4),
+            wrap_pointer: true,
+            ..Config::default()
+        };
+        let mut interp = Interpreter::new(config.clone());
+        interp.eval(">>>>+", io::empty(), io::sink()).unwrap();
+        assert_eq!((interp.ptr(), interp.cell_at(0)), (0, 1));
+        let mut interp = Interpreter::new(config);
+        interp.eval("<+", io::empty(), io::sink()).unwrap();
+        assert_eq!((interp.ptr(), interp.cell_at(3)), (3, 1));
+    }
+
+    #[test]
+    fn cells_overflow_at_their_width() {
+        let code = "+".repeat(256);
+        let cell = |cell_size| {
+            let mut interp = Interpreter::new(Config {
+                cell_size,
+                ..Config::default()
+            });
+            interp.eval(&code, io::empty(), io::sink()).unwrap();
+            interp.cell_at(0)
+        };
+        assert_eq!(cell(CellSize::U8), 0);
+        assert_eq!(cell(CellSize::U16), 256);
+    }
+
+    #[test]
+    fn tokenize_skips_comment_bytes() {
+        assert_eq!(tokenize(b"a+b"), [Plus]);
+    }
+
+    #[test]
+    fn utf8_output_of_e_acute() {
+        let run = |cell_size, ops| {
+            let config = Config {
+                utf8: true,
+                cell_size,
+                ..Config::default()
+            };
+            let mut interp = Interpreter::from_ops(config, ops).unwrap();
+            let mut out = Vec::new();
+            interp.run(io::empty(), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // The two bytes of the encoding, written one at a time
+        assert_eq!(run(CellSize::U8, vec![Add(0xc3), Dot, Add(-26), Dot]), "é");
+        // The whole code point in one cell
+        assert_eq!(run(CellSize::U32, vec![Add(0xe9), Dot]), "é");
+    }
+
+    #[test]
+    fn init_tape_loads_cells_from_a_file() {
+        let path = std::env::temp_dir().join(format!("bf-init-{}", std::process::id()));
+        std::fs::write(&path, b"aBc").unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut interp = Interpreter::new(Config::default());
+        interp.init_tape(&data).unwrap();
+        let mut out = Vec::new();
+        interp.eval(">.", io::empty(), &mut out).unwrap();
+        assert_eq!(out, b"B");
+
+        let mut small = Interpreter::new(Config {
+            tape_size: Some(2),
+            ..Config::default()
+        });
+        assert!(matches!(
+            small.init_tape(&data),
+            Err(BfError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn step_back_undoes_recorded_steps() {
+        let mut interp = Interpreter::new(Config {
+            opt_level: OptLevel::O0,
+            record: true,
+            ..Config::default()
+        });
+        interp.load("+>+").unwrap();
+        for _ in 0..3 {
+            assert!(interp.step_forward(io::empty(), io::sink()).unwrap());
+        }
+        assert_eq!(
+            (interp.pc(), interp.ptr(), interp.tape()),
+            (3, 1, &[1, 1][..])
+        );
+        assert!(interp.step_back());
+        assert!(interp.step_back());
+        assert_eq!((interp.pc(), interp.ptr(), interp.tape()[0]), (1, 0, 1));
+        assert_eq!(interp.tape().get(1).copied().unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn halt_stops_before_later_output() {
+        let mut interp = Interpreter::new(Config {
+            extensions: true,
+            ..Config::default()
+        });
+        let mut out = Vec::new();
+        interp.eval("@+.", io::empty(), &mut out).unwrap();
+        assert_eq!(out, b"");
+        assert_eq!(interp.cell_at(0), 0);
+    }
+
+    /// Hands out one byte per read
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.is_empty()) {
+                (Some((&c, rest)), false) => {
+                    buf[0] = c;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn reader_in_tiny_chunks_parses_like_the_whole_source() {
+        let code = b"read ,\n[{12}>+<-] ? @ & # ~\n>[.]";
+        let opts = ParseOptions {
+            debug: true,
+            extensions: true,
+            ..ParseOptions::default()
+        };
+        let whole = parse_with(code, opts).unwrap();
+        assert_eq!(parse_reader_with(Trickle(code), opts).unwrap(), whole);
+    }
+
+    /// Logs each write, flush and read to a shared list of events
+    #[derive(Clone, Default)]
+    struct Events(std::rc::Rc<std::cell::RefCell<Vec<&'static str>>>);
+
+    impl Write for Events {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().push("write");
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.borrow_mut().push("flush");
+            Ok(())
+        }
+    }
+
+    impl Read for Events {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.borrow_mut().push("read");
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn unbuffered_output_is_flushed_before_a_read() {
+        let mut interp = Interpreter::new(Config {
+            unbuffered: true,
+            ..Config::default()
+        });
+        let events = Events::default();
+        interp.eval("+.,", events.clone(), events.clone()).unwrap();
+        let log = events.0.borrow();
+        let read = log.iter().position(|&event| event == "read").unwrap();
+        assert_eq!(log[0], "write");
+        assert_eq!(log[read - 1], "flush");
+    }
+
+    /// Fails with `Interrupted` on the first read, then reads `b'A'`
+    struct InterruptedOnce(bool);
+
+    impl Read for InterruptedOnce {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.0 {
+                self.0 = true;
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            buf[0] = b'A';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn interrupted_read_is_retried() {
+        let mut interp = Interpreter::new(Config::default());
+        let mut out = Vec::new();
+        interp.eval(",.", InterruptedOnce(false), &mut out).unwrap();
+        assert_eq!(out, b"A");
+    }
+
+    #[test]
+    fn debug_dump_marks_the_current_cell() {
+        let mut interp = Interpreter::new(Config {
+            debug: true,
+            quiet: true,
+            ..Config::default()
+        });
+        let mut out = Vec::new();
+        interp.eval("+++#", io::empty(), &mut out).unwrap();
+        assert_eq!(out, b"");
+        let window = debug_window(interp.tape().len(), interp.ptr(), |i| interp.cell_at(i));
+        assert_eq!(window, "#: ptr=0 | [3]");
+        assert_eq!(
+            debug_window(20, 10, |i| i),
+            "#: ptr=10 | 6 7 8 9 [10] 11 12 13 14"
+        );
+    }
+
+    #[test]
+    fn signed_cells_read_and_print_negative_numbers() {
+        let config = Config {
+            numeric: true,
+            signed: true,
+            ..Config::default()
+        };
+        let mut interp = Interpreter::new(config.clone());
+        let mut out = Vec::new();
+        interp.eval("-.", io::empty(), &mut out).unwrap();
+        assert_eq!(out, b"-1 ");
+
+        let mut interp = Interpreter::new(config);
+        interp.eval(",>,", &b"-5 -128"[..], io::sink()).unwrap();
+        assert_eq!(interp.tape(), [251, 0x80]);
+    }
+
+    #[test]
+    fn profile_counts_the_inner_loop_more() {
+        let mut interp = Interpreter::new(Config {
+            profile: true,
+            opt_level: OptLevel::O0,
+            ..Config::default()
+        });
+        interp
+            .eval("+++[>++[>+<-]<-]", io::empty(), io::sink())
+            .unwrap();
+        assert_eq!(interp.stats().hot_loops(), [(7, 3), (3, 2)]);
+    }
+
+    #[test]
+    fn escaped_output_shows_control_bytes() {
+        let mut interp = Interpreter::new(Config {
+            escape_output: true,
+            ..Config::default()
+        });
+        let mut out = Vec::new();
+        interp
+            .eval(
+                "+++++++.+++.>++++++++[<++++++++>-]<+.",
+                io::empty(),
+                &mut out,
+            )
+            .unwrap();
+        assert_eq!(out, b"\\x07\nK");
+    }
+
+    #[test]
+    fn start_offset_gives_room_to_the_left() {
+        let config = Config {
+            start_offset: 5,
+            opt_level: OptLevel::O0,
+            ..Config::default()
+        };
+        let mut interp = Interpreter::new(config.clone());
+        interp.eval("<<<<<+", io::empty(), io::sink()).unwrap();
+        assert_eq!((interp.ptr(), interp.cell_at(0)), (0, 1));
+        let result = Interpreter::new(config).eval("<<<<<<", io::empty(), io::sink());
+        assert!(matches!(
+            result,
+            Err(BfError::PointerOutOfBounds { pc: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn output_stops_at_max_output() {
+        let mut interp = Interpreter::new(Config {
+            max_output: 10,
+            ..Config::default()
+        });
+        let mut out = Vec::new();
+        let result = interp.eval("+[.]", io::empty(), &mut out);
+        assert!(matches!(
+            result,
+            Err(BfError::OutputLimitExceeded { limit: 10 })
+        ));
+        assert_eq!(out, [1; 10]);
+    }
+
+    #[test]
+    fn print_int_prints_the_cell_in_decimal() {
+        let mut interp = Interpreter::new(Config {
+            extensions: true,
+            ..Config::default()
+        });
+        let mut out = Vec::new();
+        interp
+            .eval(">++++++++[<++++++++>-]<+&.", io::empty(), &mut out)
+            .unwrap();
+        assert_eq!(out, b"65A");
+    }
+
+    #[test]
+    fn tape_helpers_apply_the_bounds_policy() {
+        let mut interp = Interpreter::new(Config::default());
+        *interp.cell_mut() = 7;
+        assert_eq!(interp.cell(), 7);
+        assert!(interp.move_left().is_err());
+        assert_eq!(interp.ptr, 0);
+        interp.move_right().unwrap();
+        assert_eq!((interp.ptr, interp.cell(), interp.tape.len()), (1, 0, 2));
+
+        let mut bounded = Interpreter::new(Config {
+            tape_size: Some(2),
+            ..Config::default()
+        });
+        bounded.move_right().unwrap();
+        assert!(matches!(
+            bounded.move_right(),
+            Err(BfError::PointerOutOfBounds { position: 2, .. })
+        ));
+        assert_eq!(bounded.ptr, 1);
+
+        let mut wrapping = Interpreter::new(Config {
+            tape_size: Some(2),
+            wrap_pointer: true,
+            ..Config::default()
+        });
+        wrapping.move_left().unwrap();
+        assert_eq!(wrapping.ptr, 1);
+        wrapping.move_right().unwrap();
+        assert_eq!(wrapping.ptr, 0);
+    }
+}
@@ -0,0 +1,265 @@
+//! Static checks for likely mistakes in brainfuck programs
+
+use crate::BfOp::{self, *};
+use crate::{line_col, match_brackets, parse_offsets, tokenize_offsets, ParseError, ParseOptions};
+use std::fmt;
+
+/// A suspicious construct found in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub col: usize,
+    /// The length in bytes of the source of the loop
+    pub length: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.col, self.message
+        )
+    }
+}
+
+/// Checks a program for loops that can never terminate once entered, and
+/// loops that can never be entered
+pub fn lint(code: &[u8], opts: ParseOptions) -> Result<Vec<Warning>, ParseError> {
+    let (mut tokens, offsets) = tokenize_offsets(code, opts);
+    match_brackets(&mut tokens, |i| line_col(code, offsets[i]))?;
+    let mut loops = endless_loops(&tokens);
+    loops.extend(dead_loops(&tokens));
+    loops.sort_by_key(|&(i, _)| i);
+    Ok(loops
+        .into_iter()
+        .map(|(i, message)| {
+            let (line, col) = line_col(code, offsets[i]);
+            let end = match tokens[i] {
+                LBracket(j) => offsets[j],
+                _ => offsets[i],
+            };
+            let length = end - offsets[i] + 1;
+            Warning {
+                line,
+                col,
+                length,
+                message,
+            }
+        })
+        .collect())
+}
+
+/// How serious a `Diagnostic` is
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the source, located for an editor to highlight
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+    /// The length in bytes of the source it covers
+    pub length: usize,
+}
+
+/// Parses a program, returning its parse error if it has one, or otherwise
+/// the warnings from `lint` if `lint` is set
+///
+/// ```
+/// use brainfuck::lint::{self, Severity};
+/// use brainfuck::ParseOptions;
+///
+/// let diagnostics = lint::diagnostics(b"+\n+[[-]", ParseOptions::default(), true);
+/// assert_eq!(diagnostics.len(), 1);
+/// assert_eq!(diagnostics[0].severity, Severity::Error);
+/// assert_eq!((diagnostics[0].line, diagnostics[0].col), (2, 2));
+/// assert_eq!(
+///     lint::to_json(&diagnostics),
+///     r#"[{"severity":"error","message":"unmatched left bracket","line":2,"column":2,"length":1}]"#
+/// );
+///
+/// let warnings = lint::diagnostics(b"[-]+[]", ParseOptions::default(), true);
+/// assert_eq!(warnings.len(), 2);
+/// assert!(warnings.iter().all(|d| d.severity == Severity::Warning));
+/// ```
+pub fn diagnostics(code: &[u8], opts: ParseOptions, lint: bool) -> Vec<Diagnostic> {
+    let error = match parse_offsets(code, opts) {
+        Ok(_) if lint => {
+            let warnings = self::lint(code, opts).unwrap_or_default();
+            return warnings
+                .into_iter()
+                .map(|w| Diagnostic {
+                    severity: Severity::Warning,
+                    message: w.message.to_string(),
+                    line: w.line,
+                    col: w.col,
+                    length: w.length,
+                })
+                .collect();
+        }
+        Ok(_) => return Vec::new(),
+        Err(e) => e,
+    };
+    let (line, col) = error.position();
+    let message = match error {
+        ParseError::UnmatchedLeftBracket { .. } => "unmatched left bracket".to_string(),
+        ParseError::UnmatchedRightBracket { .. } => "unmatched right bracket".to_string(),
+        ParseError::UnexpectedCharacter { c, .. } => format!("unexpected character {:?}", c),
+        ParseError::InvalidLiteral { .. } => "invalid literal; expected {digits}".to_string(),
+    };
+    let length = match error {
+        ParseError::InvalidLiteral { .. } => {
+            // The brace, its digits and any closing brace
+            let rest = &code[offset_of(code, line, col) + 1..];
+            let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+            1 + digits + (rest.get(digits) == Some(&b'}')) as usize
+        }
+        _ => 1,
+    };
+    vec![Diagnostic {
+        severity: Severity::Error,
+        message,
+        line,
+        col,
+        length,
+    }]
+}
+
+/// The byte offset of a 1-based line and column in `code`
+fn offset_of(code: &[u8], line: usize, col: usize) -> usize {
+    let lines = code.split(|&c| c == b'\n').take(line - 1);
+    let start: usize = lines.map(|l| l.len() + 1).sum();
+    (start + col - 1).min(code.len())
+}
+
+/// Formats diagnostics as a JSON array of objects with `severity`,
+/// `message`, `line`, `column` and `length` fields
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    let objects: Vec<_> = diagnostics
+        .iter()
+        .map(|d| {
+            let severity = match d.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            format!(
+                "{{\"severity\":\"{}\",\"message\":{},\"line\":{},\"column\":{},\"length\":{}}}",
+                severity,
+                json_string(&d.message),
+                d.line,
+                d.col,
+                d.length
+            )
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+/// Quotes a string for JSON
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Finds loops whose body has no nested loops, input, random bytes or halts,
+/// returns the pointer to where it started, and leaves the current cell
+/// unchanged
+fn endless_loops(tokens: &[BfOp]) -> Vec<(usize, &'static str)> {
+    let mut loops = Vec::new();
+    for (i, op) in tokens.iter().enumerate() {
+        if let LBracket(j) = *op {
+            let mut offset = 0isize;
+            let mut delta = 0i32;
+            let mut simple = true;
+            for op in &tokens[i + 1..j] {
+                match op {
+                    Gt => offset += 1,
+                    Lt => offset -= 1,
+                    Plus if offset == 0 => delta += 1,
+                    Minus if offset == 0 => delta -= 1,
+                    Comma | Random | Reset | SetValue(_) | Halt | LBracket(_) => {
+                        simple = false;
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+            if simple && offset == 0 && delta == 0 {
+                loops.push((i, "loop never changes its controlling cell"));
+            }
+        }
+    }
+    loops
+}
+
+/// Finds loops reached before any cell has changed, or right after another
+/// loop has exited, so their cell is always zero
+fn dead_loops(tokens: &[BfOp]) -> Vec<(usize, &'static str)> {
+    let mut loops = Vec::new();
+    let mut untouched = true;
+    let mut i = 0;
+    while i < tokens.len() {
+        match tokens[i] {
+            LBracket(j)
+                if untouched || matches!(tokens.get(i.wrapping_sub(1)), Some(RBracket(_))) =>
+            {
+                loops.push((i, "loop is never entered because its cell is always zero"));
+                i = j;
+            }
+            LBracket(_) | Plus | Minus | Comma | Random | SetValue(_) => untouched = false,
+            _ => (),
+        }
+        i += 1;
+    }
+    loops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extended() -> ParseOptions {
+        ParseOptions {
+            extensions: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    #[test]
+    fn halting_loop_is_not_endless() {
+        assert_eq!(lint(b"+[@]", extended()).unwrap(), []);
+    }
+
+    #[test]
+    fn loop_that_never_changes_its_cell_is_endless() {
+        let warnings = lint(b"+[>+<]", ParseOptions::default()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].line, warnings[0].col), (1, 2));
+        assert_eq!(warnings[0].length, 5);
+        assert_eq!(lint(b"+[-]", ParseOptions::default()).unwrap(), []);
+    }
+
+    #[test]
+    fn loop_at_the_start_is_never_entered() {
+        let warnings = lint(b"[+.]", ParseOptions::default()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].line, warnings[0].col), (1, 1));
+        assert_eq!(lint(b"+[+.]", ParseOptions::default()).unwrap(), []);
+    }
+}
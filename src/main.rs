@@ -1,138 +1,993 @@
 use anyhow::Error;
-use std::fs::File;
-use std::io::{self, Read, Write};
+use brainfuck::bytecode;
+use brainfuck::emit::{self, Target};
+#[cfg(feature = "memory-view")]
+use brainfuck::view;
+use brainfuck::{
+    bench, check_optimization, format, lint, locate_part, parse_with, preprocess, tokenize_with,
+    Ascii7, BfError, CellSize, Config, Dialect, Eof, Interpreter, LineReader, OptLevel, ParseError,
+    Stats, Tee, TeeReader, IDENTITY_MAP,
+};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+#[cfg(all(unix, feature = "interrupt"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
-use thiserror::Error;
+
+/// Where `--break` pauses the program
+#[derive(Debug, Copy, Clone)]
+enum Breakpoint {
+    /// An index into the loaded instructions
+    Instruction(usize),
+    /// A byte offset into the source
+    Source(usize),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (source, n) = match s.strip_prefix('@') {
+            Some(n) => (true, n),
+            None => (false, s),
+        };
+        let n = n
+            .parse()
+            .map_err(|_| format!("Invalid breakpoint: {}", s))?;
+        Ok(if source {
+            Breakpoint::Source(n)
+        } else {
+            Breakpoint::Instruction(n)
+        })
+    }
+}
 
 #[derive(StructOpt, Debug)]
 struct Opt {
-    /// A brainfuck source file or - for stdin
+    /// Brainfuck source files, joined in order into one program, or - for
+    /// stdin
     #[structopt(parse(from_os_str), default_value = "-")]
-    file: PathBuf,
-    /// An inline brainfuck program
-    #[structopt(short, long, conflicts_with = "file")]
-    command: Option<String>,
-    /// Enable debug prints
+    files: Vec<PathBuf>,
+    /// An inline brainfuck program; given more than once, the parts are
+    /// joined with newlines into one program
+    #[structopt(
+        short,
+        long,
+        number_of_values = 1,
+        allow_hyphen_values = true,
+        conflicts_with = "files"
+    )]
+    command: Vec<String>,
+    /// Enable debug prints and the # instruction to dump nearby cells
     #[structopt(short, long)]
     debug: bool,
+    /// With --debug, print every instruction instead of a summary
+    #[structopt(long, requires = "debug")]
+    debug_full: bool,
+    /// Read and run brainfuck interactively one line at a time; lines
+    /// starting with : are commands: :tape, :ptr, :reset and :load <file>
+    #[structopt(long, conflicts_with_all = &["command", "emit", "step"])]
+    repl: bool,
+    /// Enable extension instructions: @ halts the program, & prints the
+    /// current cell as a number, ? stores a random byte in it, ~ zeroes
+    /// the tape and moves back to the start and {65} sets it to 65
+    #[structopt(short = "x", long)]
+    extensions: bool,
+    /// With --extensions, the character that prints the current cell as a number
+    /// [default: &]
+    #[structopt(long, parse(try_from_str = parse_char), requires = "extensions")]
+    print_int: Option<u8>,
+    /// With --extensions, reject characters other than commands and
+    /// whitespace instead of treating them as comments
+    #[structopt(long, requires = "extensions")]
+    strict: bool,
+    /// With --extensions, seed the random bytes stored by ? so runs are
+    /// reproducible [default: the current time]
+    #[structopt(long, requires = "extensions")]
+    seed: Option<u64>,
+    /// Step through the program one instruction at a time, controlled from
+    /// the terminal: press Enter to step, enter b to step back (with --record)
+    /// or c to continue
+    #[structopt(long)]
+    step: bool,
+    /// Run until this instruction, or the command at this source byte offset
+    /// if prefixed with @, then start stepping (repeatable); source offsets
+    /// disable optimization so that each command is one instruction
+    #[structopt(
+        long = "break",
+        value_name = "pos",
+        number_of_values = 1,
+        conflicts_with = "repl"
+    )]
+    breakpoints: Vec<Breakpoint>,
+    /// Write a line for each executed instruction to this file, with
+    /// tab-separated columns for the instruction index, pointer, current cell
+    /// and instruction name
+    #[structopt(
+        long,
+        value_name = "path",
+        parse(from_os_str),
+        conflicts_with_all = &["step", "repl", "breakpoints", "jit"]
+    )]
+    trace: Option<PathBuf>,
+    /// Compile the program to x86-64 machine code instead of interpreting it
+    /// (requires the jit feature)
+    #[structopt(long, conflicts_with_all = &["step", "repl", "breakpoints"])]
+    jit: bool,
+    /// Show the cells around the pointer live in the terminal while the
+    /// program runs, with its output in a pane below and not written to
+    /// stdout (requires the memory-view feature)
+    #[structopt(long, conflicts_with_all = &["step", "repl", "breakpoints", "jit", "trace"])]
+    memory_view: bool,
+    /// With --memory-view, the most times to redraw the view each second
+    /// [default: 30]
+    #[structopt(long, value_name = "fps", parse(try_from_str = parse_rate), requires = "memory-view")]
+    refresh_rate: Option<f64>,
+    /// Record changes so the step debugger can step backwards
+    #[structopt(long, requires = "step")]
+    record: bool,
+    /// Optimization level: 0 runs the program as written, 1 folds runs and
+    /// clear loops, 2 also folds scan and multiplication loops and pointer offsets
+    #[structopt(short = "O", long = "optimize", default_value = "2", possible_values = &["0", "1", "2"])]
+    opt_level: OptLevel,
+    /// Fold runs of `.` into single writes (ignored at -O0)
+    #[structopt(long)]
+    fold_io: bool,
+    /// Maximum number of cells on the tape [default: unbounded]
+    #[structopt(long)]
+    tape_size: Option<usize>,
+    /// Wrap the pointer around the ends of the tape (requires a tape size)
+    #[structopt(long)]
+    wrap_pointer: bool,
+    /// Start the pointer at this cell, leaving room to move left
+    #[structopt(long, default_value = "0")]
+    start_offset: usize,
+    /// Store only the cells that are written, for programs that touch a few
+    /// far-apart cells; slower, and not supported by --jit
+    #[structopt(long, conflicts_with = "jit")]
+    sparse: bool,
+    /// Grow the tape to the left too when the pointer moves past the first
+    /// cell, instead of failing
+    #[structopt(
+        long,
+        conflicts_with_all = &["tape-size", "wrap-pointer", "record", "jit"]
+    )]
+    bidirectional: bool,
+    /// Bits per cell: 8, 16 or 32 [default: 8]
+    #[structopt(long, possible_values = &["8", "16", "32"])]
+    cell_size: Option<CellSize>,
+    /// Cell value after reading EOF: zero, minus-one or unchanged [default: zero]
+    #[structopt(long, possible_values = &["zero", "minus-one", "unchanged"])]
+    eof: Option<Eof>,
+    /// After the first read that reaches EOF, give every later , the EOF
+    /// value without reading again
+    #[structopt(long)]
+    sticky_eof: bool,
+    /// Preset the tape size, wrapping, cell size and EOF behavior to match an
+    /// interpreter, which the other options override: classic (30000 cells),
+    /// bff (30000 wrapping cells, EOF unchanged) or urban (unbounded)
+    #[structopt(long, possible_values = &["classic", "bff", "urban"])]
+    dialect: Option<Dialect>,
+    /// Read and write cells as decimal numbers instead of bytes
+    #[structopt(long)]
+    numeric: bool,
+    /// With --numeric, read and write cells as signed numbers
+    #[structopt(long, requires = "numeric")]
+    signed: bool,
+    /// Read and write UTF-8 text, as bytes with 8-bit cells or as code points
+    /// with wider cells
+    #[structopt(long, conflicts_with = "numeric")]
+    utf8: bool,
+    /// Write non-printable output bytes as \xNN escapes
+    #[structopt(long, conflicts_with_all = &["numeric", "utf8"])]
+    escape_output: bool,
+    /// Translate bytes read by , through comma-separated from=to pairs, where
+    /// each side is a character or a decimal byte value, as in A=1,B=2
+    #[structopt(
+        long,
+        value_name = "pairs",
+        parse(try_from_str = parse_byte_map),
+        conflicts_with_all = &["numeric", "utf8"]
+    )]
+    input_map: Option<[u8; 256]>,
+    /// Translate bytes written by . through from=to pairs, like --input-map
+    #[structopt(
+        long,
+        value_name = "pairs",
+        parse(try_from_str = parse_byte_map),
+        conflicts_with_all = &["numeric", "utf8"]
+    )]
+    output_map: Option<[u8; 256]>,
+    /// Clear the high bit of each output byte, for 7-bit ASCII channels
+    #[structopt(long, conflicts_with_all = &["numeric", "utf8", "ascii7-strict"])]
+    ascii7: bool,
+    /// Fail on output bytes above 127 instead of masking them
+    #[structopt(long, conflicts_with_all = &["numeric", "utf8"])]
+    ascii7_strict: bool,
+    /// Write a newline after running if the output didn't end with one
+    #[structopt(long)]
+    newline_at_end: bool,
+    /// Read program input from a file instead of stdin
+    #[structopt(short, long, parse(from_os_str))]
+    input: Option<PathBuf>,
+    /// Use the given text as program input instead of stdin
+    #[structopt(long, conflicts_with = "input")]
+    input_string: Option<String>,
+    /// Read input a line at a time, so a , with nothing left to read waits
+    /// for a whole line and the following ones read the rest of it
+    #[structopt(long)]
+    line_input: bool,
+    /// Copy every byte of input the program reads to this file, so the run
+    /// can be reproduced with --replay-input
+    #[structopt(long, parse(from_os_str))]
+    record_input: Option<PathBuf>,
+    /// Read program input from a file written by --record-input instead of
+    /// stdin
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["input", "input-string", "record-input", "split-on", "bang"])]
+    replay_input: Option<PathBuf>,
+    /// Read the program's input from the source after the first occurrence of
+    /// this character, which can't be a command
+    #[structopt(long, parse(try_from_str = parse_char), conflicts_with_all = &["input", "input-string", "run-bytecode", "repl"])]
+    split_on: Option<u8>,
+    /// Read the program's input from the source after the first !, like
+    /// --split-on !
+    #[structopt(long, conflicts_with_all = &["split-on", "input", "input-string", "run-bytecode", "repl", "preprocess"])]
+    bang: bool,
+    /// Write program output to a file instead of stdout
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Also copy program output to stderr
+    #[structopt(long)]
+    tee: bool,
+    /// Print the most iterated loops to stderr after running
+    #[structopt(long)]
+    profile: bool,
+    /// Flush the output after every character, so prompts appear before reading input
+    #[structopt(short, long)]
+    unbuffered: bool,
+    /// Bytes of output to collect before writing them, or 0 to write as the
+    /// program runs; collected output is always written before reading input
+    #[structopt(long, default_value = "8192")]
+    output_buffer: usize,
+    /// Print instruction counts to stderr after running
+    #[structopt(long)]
+    stats: bool,
+    /// Don't print diagnostics from --debug, --stats, --time, --watch or --dump-tape
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Print how long parsing and execution took to stderr
+    #[structopt(long)]
+    time: bool,
+    /// Print the results of --time, --stats and --profile to stderr as a
+    /// single JSON object
+    #[structopt(long)]
+    json: bool,
+    /// Stop with an error after this many instructions, or 0 for no limit
+    #[structopt(long, default_value = "0")]
+    max_steps: u64,
+    /// Stop with an error before writing more than this many bytes, or 0 for no limit
+    #[structopt(long, default_value = "0")]
+    max_output: u64,
+    /// Pad the output with NUL bytes to exactly this many bytes when the
+    /// program finishes, failing if it wrote more
+    #[structopt(long, value_name = "n", conflicts_with = "repl")]
+    pad_output: Option<u64>,
+    /// Stop with an error after running for this many seconds
+    #[structopt(long, value_name = "seconds", parse(try_from_str = parse_seconds))]
+    timeout: Option<Duration>,
+    /// Stop with an error if the program reads more than this many bytes of
+    /// input
+    #[structopt(long, value_name = "bytes")]
+    expect_input: Option<u64>,
+    /// Load the initial tape contents from a file of raw bytes
+    #[structopt(long, parse(from_os_str))]
+    init_tape: Option<PathBuf>,
+    /// Report every change to the cell at this index (repeatable)
+    #[structopt(long, number_of_values = 1)]
+    watch: Vec<usize>,
+    /// Print the tape contents to stderr after running
+    #[structopt(long)]
+    dump_tape: bool,
+    /// Write the tape to this file after running, one raw byte per cell up
+    /// to the highest cell touched, as --init-tape reads it
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    dump_tape_file: Option<PathBuf>,
+    /// Save the tape, pointer and tape settings to this file after running,
+    /// for --load-state to resume from
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    save_state: Option<PathBuf>,
+    /// Start from the tape and pointer saved by --save-state, which must
+    /// have used the same cell size and tape settings
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "path",
+        conflicts_with = "init-tape"
+    )]
+    load_state: Option<PathBuf>,
+    /// Exit with the low byte of cell 0 as the status after running
+    #[structopt(long, conflicts_with = "repl")]
+    exit_code: bool,
+    /// Expand `!include path` lines with the contents of the named files before parsing
+    #[structopt(long, conflicts_with = "run-bytecode")]
+    preprocess: bool,
+    /// Print the program with comments and whitespace removed instead of running it
+    #[structopt(long, conflicts_with_all = &["run-bytecode", "repl"])]
+    format: bool,
+    /// With --format, put brackets on their own lines and indent loop bodies
+    #[structopt(long, requires = "format")]
+    indent: bool,
+    /// Check the program for loops that can never terminate instead of running it
+    #[structopt(long, conflicts_with_all = &["run-bytecode", "repl", "format"])]
+    lint: bool,
+    /// Only parse the program, printing its parse error and, with --lint, its
+    /// warnings as a JSON array of diagnostics for editors, instead of
+    /// running it
+    #[structopt(
+        long,
+        value_name = "format",
+        possible_values = &["json"],
+        conflicts_with_all = &["run-bytecode", "repl", "format", "check"]
+    )]
+    diagnostics: Option<String>,
+    /// Only check that the program's brackets are balanced instead of running it
+    #[structopt(long, conflicts_with_all = &["run-bytecode", "repl", "format", "lint"])]
+    check: bool,
+    /// Write the optimized program as bytecode to a file instead of running it
+    #[structopt(long, parse(from_os_str), conflicts_with = "emit")]
+    compile: Option<PathBuf>,
+    /// Run a bytecode file written by --compile instead of brainfuck source
+    #[structopt(long, parse(from_os_str), conflicts_with = "command")]
+    run_bytecode: Option<PathBuf>,
+    /// Load the compiled program from this directory if it was cached by an
+    /// earlier run with the same source and settings, or cache it there
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "dir",
+        conflicts_with = "run-bytecode"
+    )]
+    cache_dir: Option<PathBuf>,
+    /// Print the program translated to another language instead of running it: c, rust, dot
+    /// for a Graphviz control-flow graph, or wat for a WebAssembly text module
+    #[structopt(long, possible_values = &["c", "rust", "dot", "wat"])]
+    emit: Option<Target>,
+    /// Comment the C or Rust from --emit with the source line and column of
+    /// each statement
+    #[structopt(long, requires = "emit", conflicts_with_all = &["run-bytecode", "cache-dir"])]
+    emit_debug_info: bool,
+    /// Print the optimized instructions one per line, with their operands
+    /// and bracket targets, instead of running them
+    #[structopt(long, conflicts_with = "emit")]
+    dump_ops: bool,
+    /// Run the program this many times on the same input, discarding its
+    /// output, and print the min, median and mean run times to stderr
+    #[structopt(
+        long,
+        value_name = "iterations",
+        conflicts_with_all = &["step", "repl", "breakpoints", "trace", "jit", "memory-view", "emit", "dump-ops"]
+    )]
+    bench: Option<usize>,
+    /// Run the program unoptimized and at the chosen level on the same
+    /// input, failing if their outputs differ; for testing the optimizer,
+    /// since it runs the program twice
+    #[structopt(
+        long,
+        conflicts_with_all = &["run-bytecode", "step", "repl", "breakpoints", "trace", "jit", "memory-view", "emit", "dump-ops", "bench"]
+    )]
+    verify_opt: bool,
 }
 
-#[derive(Debug, Copy, Clone)]
-enum BfOp {
-    Gt,
-    Lt,
-    Plus,
-    Minus,
-    Dot,
-    Comma,
-    LBracket(usize),
-    RBracket(usize),
-}
-
-#[derive(Debug, Copy, Clone, Error)]
-enum ParseError {
-    #[error("Unmatched left bracket")]
-    UnmatchedLeftBracket,
-    #[error("Unmatched right bracket")]
-    UnmatchedRightBracket,
-}
-
-#[derive(Debug, Error)]
-enum BfError {
-    #[error("Invalid brainfuck syntax: {0}")]
-    ParseError(#[from] ParseError),
-    #[error("Runtime error: {0}")]
-    RuntimeError(#[from] io::Error),
-}
-
-use BfOp::*;
-use ParseError::*;
-
-fn parse(code: &str) -> Result<Vec<BfOp>, ParseError> {
-    let mut instrs: Vec<_> = code
-        .bytes()
-        .filter_map(|c| match c {
-            b'>' => Some(Gt),
-            b'<' => Some(Lt),
-            b'+' => Some(Plus),
-            b'-' => Some(Minus),
-            b'.' => Some(Dot),
-            b',' => Some(Comma),
-            b'[' => Some(LBracket(0)),
-            b']' => Some(RBracket(0)),
-            _ => None,
-        })
-        .collect();
-    let mut brackets = Vec::new();
-    for i in 0..instrs.len() {
-        match instrs[i] {
-            LBracket(_) => brackets.push(i),
-            RBracket(_) => {
-                let j = brackets.pop().ok_or(UnmatchedRightBracket)?;
-                instrs[j] = LBracket(i);
-                instrs[i] = RBracket(j);
-            }
-            _ => (),
+/// Parses an option naming a single byte that isn't already a command
+fn parse_char(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [c] if b"<>+-.,[]#@?~".contains(c) => Err(format!("{} is a brainfuck command", s)),
+        &[c] => Ok(c),
+        _ => Err(format!("Not a single character: {}", s)),
+    }
+}
+
+/// Parses a byte for a byte map, as decimal digits or a single character
+fn parse_map_byte(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [] => Err("Missing byte in map".to_string()),
+        digits if digits.iter().all(u8::is_ascii_digit) => {
+            s.parse().map_err(|_| format!("Byte out of range: {}", s))
         }
+        &[c] => Ok(c),
+        _ => Err(format!("Not a single character or byte value: {}", s)),
+    }
+}
+
+/// Parses comma-separated from=to pairs into a byte map that leaves other
+/// bytes unchanged
+fn parse_byte_map(s: &str) -> Result<[u8; 256], String> {
+    let mut map = IDENTITY_MAP;
+    for pair in s.split(',') {
+        let (from, to) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("Expected from=to: {}", pair))?;
+        map[parse_map_byte(from)? as usize] = parse_map_byte(to)?;
     }
-    if !brackets.is_empty() {
-        return Err(UnmatchedLeftBracket);
+    Ok(map)
+}
+
+/// Parses a positive number of frames per second
+fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        _ => Err(format!("Invalid refresh rate: {}", s)),
     }
-    Ok(instrs)
 }
 
-fn run(code: &str, opts: &Opt) -> Result<(), BfError> {
-    let instrs = parse(code)?;
-    if opts.debug {
-        println!("{:?}", instrs);
+/// Parses a nonnegative number of seconds
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs >= 0.0 && secs.is_finite() => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("Invalid number of seconds: {}", s)),
     }
-    let mut stdout = io::stdout().lock();
-    let mut stdin = io::stdin().lock();
-    let mut tape = [0u8; 1 << 16];
-    let mut pc = 0;
-    let mut ptr = 0;
-    while pc < instrs.len() {
-        match instrs[pc] {
-            Gt => ptr += 1,
-            Lt => ptr -= 1,
-            Plus => tape[ptr] = tape[ptr].wrapping_add(1),
-            Minus => tape[ptr] = tape[ptr].wrapping_sub(1),
-            Dot => {
-                stdout.write(&tape[ptr..=ptr])?;
-            }
-            Comma => {
-                tape[ptr] = 0;
-                stdin.read(&mut tape[ptr..=ptr])?;
+}
+
+/// Opens a brainfuck source file, or stdin for -
+fn open_source(path: &Path) -> io::Result<Box<dyn Read>> {
+    Ok(match path {
+        p if p == Path::new("-") => Box::new(io::stdin()),
+        path => Box::new(File::open(path)?),
+    })
+}
+
+/// Reads the source files one after another into a single program,
+/// returning it with the offset each file starts at
+fn read_sources(opt: &Opt) -> io::Result<(Vec<u8>, Vec<usize>)> {
+    let mut code = Vec::new();
+    let mut starts = Vec::new();
+    for path in &opt.files {
+        starts.push(code.len());
+        open_source(path)?.read_to_end(&mut code)?;
+    }
+    Ok((code, starts))
+}
+
+/// Gives the file and the line and column in it of a parse error in a
+/// program joined from several files by `read_sources`
+fn locate_parse_error(e: ParseError, opt: &Opt, code: &[u8], starts: &[usize]) -> Error {
+    if starts.len() < 2 {
+        return e.into();
+    }
+    let (line, col) = e.position();
+    let (part, line, col) = locate_part(code, starts, line, col);
+    let name = match &opt.files[part] {
+        p if p == Path::new("-") => "stdin".to_string(),
+        p => p.display().to_string(),
+    };
+    anyhow::anyhow!("{} in {}", e.at_position(line, col), name)
+}
+
+/// Reads all of the program's input, for running it more than once
+fn read_input(opt: &Opt, split_input: Option<Vec<u8>>, split: bool) -> io::Result<Vec<u8>> {
+    let mut input = Vec::new();
+    match split_input {
+        Some(rest) => input = rest,
+        // Input after a separator is all there is
+        None if split => {}
+        None => {
+            open_input(opt)?.read_to_end(&mut input)?;
+        }
+    }
+    Ok(input)
+}
+
+/// Opens the program's input stream
+fn open_input(opt: &Opt) -> io::Result<Box<dyn Read>> {
+    // These options are mutually exclusive
+    Ok(match (&opt.input, &opt.replay_input, &opt.input_string) {
+        (Some(path), _, _) | (_, Some(path), _) => Box::new(BufReader::new(File::open(path)?)),
+        (_, _, Some(text)) => Box::new(io::Cursor::new(text.clone().into_bytes())),
+        _ => Box::new(io::stdin()),
+    })
+}
+
+/// Applies --line-input and --record-input to the program's input
+fn wrap_input(opt: &Opt, input: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let input: Box<dyn Read> = match opt.line_input {
+        true => Box::new(LineReader::new(BufReader::new(input))),
+        false => input,
+    };
+    Ok(match &opt.record_input {
+        // Unbuffered, so the log survives the program being killed
+        Some(path) => Box::new(TeeReader(input, File::create(path)?)),
+        None => input,
+    })
+}
+
+/// Opens the program's output stream
+fn open_output(opt: &Opt) -> io::Result<Box<dyn Write>> {
+    let output: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    Ok(match opt.tee {
+        true => Box::new(Tee(output, io::stderr())),
+        false => output,
+    })
+}
+
+/// Set by the SIGINT handler
+#[cfg(all(unix, feature = "interrupt"))]
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes Ctrl-C stop the interpreter so the state it reached can be
+/// reported; a second Ctrl-C kills the process as usual
+#[cfg(all(unix, feature = "interrupt"))]
+fn catch_interrupt(interp: &mut Interpreter) {
+    extern "C" fn handle(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        // Safety: signal is async-signal-safe
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+    // Safety: the handler only touches an atomic and calls signal
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    interp.set_interrupt(&INTERRUPTED);
+}
+
+#[cfg(not(all(unix, feature = "interrupt")))]
+fn catch_interrupt(_: &mut Interpreter) {}
+
+/// Runs the loaded program with the JIT compiler
+#[cfg(feature = "jit")]
+fn run_jit(interp: &mut Interpreter, input: impl Read, output: impl Write) -> Result<(), Error> {
+    Ok(interp.run_jit(input, output)?)
+}
+
+#[cfg(not(feature = "jit"))]
+fn run_jit(_: &mut Interpreter, _: impl Read, _: impl Write) -> Result<(), Error> {
+    Err(anyhow::anyhow!(
+        "--jit requires building with the jit feature"
+    ))
+}
+
+/// Formats the results of --time, --stats and --profile as a JSON object
+fn json_report(opt: &Opt, stats: &Stats, parse_time: Duration, run_time: Duration) -> String {
+    let mut fields = Vec::new();
+    if opt.time {
+        fields.push(format!("\"parse_seconds\":{}", parse_time.as_secs_f64()));
+        fields.push(format!("\"run_seconds\":{}", run_time.as_secs_f64()));
+    }
+    if opt.stats || opt.profile {
+        fields.push(format!("\"stats\":{}", stats.to_json()));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Runs a REPL command, the part of a line after its leading `:`
+fn meta_command(
+    interp: &mut Interpreter,
+    command: &str,
+    input: impl Read,
+    output: impl Write,
+) -> Result<(), Error> {
+    let mut words = command.splitn(2, char::is_whitespace);
+    match (words.next().unwrap_or(""), words.next().map(str::trim)) {
+        ("tape", None) => interp.dump_tape(io::stdout())?,
+        ("ptr", None) => println!("{}", interp.ptr()),
+        ("reset", None) => interp.reset(),
+        ("load", Some(path)) if !path.is_empty() => {
+            let code =
+                fs::read(path).map_err(|e| anyhow::anyhow!("Cannot load {}: {}", path, e))?;
+            if let Err(e) = interp.eval(&code, input, output) {
+                return Err(match interp.error_context(&e, &code) {
+                    Some(context) => anyhow::anyhow!("{}\n{}", e, context),
+                    None => e.into(),
+                });
             }
-            LBracket(i) => {
-                if tape[ptr] == 0 {
-                    pc = i
-                }
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unknown command :{}; expected :tape, :ptr, :reset or :load <file>",
+                command
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Reads and runs one line of brainfuck at a time from stdin, keeping the
+/// tape and pointer between lines, or runs a command if the line starts with
+/// `:`
+fn repl(
+    interp: &mut Interpreter,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> Result<(), Error> {
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("bf> ");
+        io::stdout().flush()?;
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if let Some(command) = line.trim_start().strip_prefix(':') {
+            if let Err(e) = meta_command(interp, command.trim_end(), &mut input, &mut output) {
+                eprintln!("Error: {}", e);
             }
-            RBracket(i) => {
-                if tape[ptr] != 0 {
-                    pc = i
-                }
+        } else if let Err(e) = interp.eval(&line, &mut input, &mut output) {
+            eprintln!("Error: {}", e);
+            if let Some(context) = interp.error_context(&e, line.as_bytes()) {
+                eprintln!("{}", context);
             }
         }
-        pc += 1;
+        output.flush()?;
+    }
+}
+
+/// Runs the loaded program while showing the tape on stderr
+#[cfg(feature = "memory-view")]
+fn run_memory_view(
+    interp: &mut Interpreter,
+    input: impl Read,
+    output: impl Write,
+    fps: f64,
+) -> Result<(), Error> {
+    let mut view = view::MemoryView::new(io::stderr(), fps);
+    Ok(view::run(interp, input, output, &mut view)?)
+}
+
+#[cfg(not(feature = "memory-view"))]
+fn run_memory_view(_: &mut Interpreter, _: impl Read, _: impl Write, _: f64) -> Result<(), Error> {
+    Err(anyhow::anyhow!(
+        "--memory-view requires building with the memory-view feature"
+    ))
+}
+
+/// Adds the part of the source where a runtime error happened to its
+/// message, rereading the source file if it wasn't kept in memory
+fn locate_error(e: Error, interp: &Interpreter, opt: &Opt, source: Option<Vec<u8>>) -> Error {
+    let context = e.downcast_ref::<BfError>().and_then(|err| {
+        let code = match source {
+            Some(code) => code,
+            None if opt.run_bytecode.is_some() => return None,
+            None => match opt.files.as_slice() {
+                [path] if path != Path::new("-") => fs::read(path).ok()?,
+                _ => return None,
+            },
+        };
+        interp.error_context(err, &code)
+    });
+    match context {
+        Some(context) => anyhow::anyhow!("{}\n{}", e, context),
+        None => e,
     }
-    Ok(())
 }
 
 fn main() -> Result<(), Error> {
     let opt = Opt::from_args();
-    let mut code = String::new();
-    if let Some(cmd) = &opt.command {
-        code = cmd.clone();
+    let source_breakpoints: Vec<usize> = opt
+        .breakpoints
+        .iter()
+        .filter_map(|&b| match b {
+            Breakpoint::Source(offset) => Some(offset),
+            Breakpoint::Instruction(_) => None,
+        })
+        .collect();
+    let preset = opt.dialect.map_or_else(Config::default, Dialect::config);
+    let config = Config {
+        tape_size: opt.tape_size.or(preset.tape_size),
+        wrap_pointer: opt.wrap_pointer || preset.wrap_pointer,
+        start_offset: opt.start_offset,
+        sparse: opt.sparse,
+        bidirectional: opt.bidirectional,
+        cell_size: opt.cell_size.unwrap_or(preset.cell_size),
+        eof: opt.eof.unwrap_or(preset.eof),
+        sticky_eof: opt.sticky_eof,
+        debug: opt.debug,
+        extensions: opt.extensions,
+        print_int: opt.print_int.unwrap_or(b'&'),
+        strict: opt.strict,
+        seed: opt.seed.unwrap_or_else(|| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH);
+            now.map_or(0, |d| d.as_nanos() as u64)
+        }),
+        numeric: opt.numeric,
+        signed: opt.signed,
+        utf8: opt.utf8,
+        escape_output: opt.escape_output,
+        input_map: opt.input_map.unwrap_or(IDENTITY_MAP),
+        output_map: opt.output_map.unwrap_or(IDENTITY_MAP),
+        ascii7: match (opt.ascii7, opt.ascii7_strict) {
+            (_, true) => Some(Ascii7::Reject),
+            (true, false) => Some(Ascii7::Mask),
+            (false, false) => None,
+        },
+        newline_at_end: opt.newline_at_end,
+        pad_output: opt.pad_output,
+        stats: opt.stats,
+        profile: opt.profile,
+        max_steps: opt.max_steps,
+        max_output: opt.max_output,
+        timeout: opt.timeout,
+        expect_input: opt.expect_input,
+        watch: opt.watch.clone(),
+        record: opt.record,
+        opt_level: match source_breakpoints.is_empty() {
+            true => opt.opt_level,
+            false => OptLevel::O0,
+        },
+        fold_io: opt.fold_io,
+        unbuffered: opt.unbuffered,
+        output_buffer: opt.output_buffer,
+        quiet: opt.quiet,
+    };
+    let mut interp = Interpreter::new(config);
+    if let Some(path) = &opt.init_tape {
+        interp.init_tape(&fs::read(path)?)?;
+    }
+    if let Some(path) = &opt.load_state {
+        interp.load_state(&fs::read(path)?)?;
+    }
+    if opt.repl {
+        let input = wrap_input(&opt, open_input(&opt)?)?;
+        return repl(&mut interp, input, open_output(&opt)?);
+    }
+    if opt.files.iter().filter(|p| *p == Path::new("-")).count() > 1 {
+        return Err(anyhow::anyhow!("- can only be given once, for stdin"));
+    }
+    let separator = if opt.bang { Some(b'!') } else { opt.split_on };
+    let start = Instant::now();
+    let mut split_input = None;
+    // The source as loaded, if it was read into memory
+    let mut source = None;
+    if let Some(path) = &opt.run_bytecode {
+        if !source_breakpoints.is_empty() {
+            return Err(anyhow::anyhow!("--break @offset needs brainfuck source"));
+        }
+        interp.load_ops(bytecode::decode(&fs::read(path)?)?);
+    } else if opt.command.is_empty()
+        && opt.files.len() == 1
+        && separator.is_none()
+        && source_breakpoints.is_empty()
+        && opt.cache_dir.is_none()
+        && !opt.emit_debug_info
+        && !opt.verify_opt
+        && opt.diagnostics.is_none()
+        && !(opt.preprocess || opt.lint || opt.format || opt.check)
+    {
+        // Nothing else needs the source, so parse it without loading it all
+        interp.load_reader(open_source(&opt.files[0])?)?;
     } else {
-        let mut reader: Box<dyn Read> = match &opt.file {
-            p if p == Path::new("-") => Box::new(io::stdin()),
-            path => Box::new(File::open(path)?),
+        let (mut code, mut starts) = match opt.command.is_empty() {
+            true => read_sources(&opt)?,
+            false => (opt.command.join("\n").into_bytes(), Vec::new()),
+        };
+        if let Some(sep) = separator {
+            if let Some(i) = code.iter().position(|&c| c == sep) {
+                split_input = Some(code.split_off(i + 1));
+                code.pop();
+            }
+        }
+        if opt.preprocess {
+            // Includes are relative to the source file if there is just one
+            let path = match (opt.command.is_empty(), opt.files.as_slice()) {
+                (true, [path]) if path != Path::new("-") => Some(path.as_path()),
+                _ => None,
+            };
+            code = preprocess::preprocess(&code, path)?;
+            // Positions in the expanded program no longer line up with the files
+            starts.clear();
+        }
+        let locate = |e| locate_parse_error(e, &opt, &code, &starts);
+        if opt.diagnostics.is_some() {
+            let opts = interp.config().parse_options();
+            println!(
+                "{}",
+                lint::to_json(&lint::diagnostics(&code, opts, opt.lint))
+            );
+            return Ok(());
+        }
+        if opt.lint {
+            let warnings = lint::lint(&code, interp.config().parse_options()).map_err(locate)?;
+            for warning in warnings {
+                eprintln!("warning: {}", warning);
+            }
+            return Ok(());
+        }
+        if opt.check {
+            parse_with(&code, interp.config().parse_options()).map_err(locate)?;
+            if !opt.quiet {
+                println!("OK");
+            }
+            return Ok(());
+        }
+        if opt.format {
+            let tokens = parse_with(&code, interp.config().parse_options()).map_err(locate)?;
+            match opt.indent {
+                true => print!("{}", format::indent(&tokens)),
+                false => print!("{}", format::minify(&tokens)),
+            }
+            return Ok(());
+        }
+        let cached = match &opt.cache_dir {
+            Some(dir) => bytecode::read_cache(dir, &code, interp.config()),
+            None => None,
         };
-        reader.read_to_string(&mut code)?;
+        match cached {
+            Some(instrs) => interp.load_ops(instrs),
+            None => {
+                interp.load(&code).map_err(locate)?;
+                if let Some(dir) = &opt.cache_dir {
+                    bytecode::write_cache(dir, &code, interp.config(), interp.instrs())?;
+                }
+            }
+        }
+        for &offset in &source_breakpoints {
+            // Unoptimized instructions match the commands one to one
+            let before = &code[..offset.min(code.len())];
+            interp.add_breakpoint(tokenize_with(before, interp.config().parse_options()).len());
+        }
+        source = Some(code);
+    }
+    for &b in &opt.breakpoints {
+        if let Breakpoint::Instruction(pc) = b {
+            interp.add_breakpoint(pc);
+        }
+    }
+    let parse_time = start.elapsed();
+    if opt.time && !opt.json && !opt.quiet {
+        eprintln!("Parsed in {:?}", parse_time);
+    }
+    if let Some(path) = &opt.compile {
+        fs::write(path, bytecode::encode(interp.instrs()))?;
+        return Ok(());
+    }
+    if opt.dump_ops {
+        print!("{}", format::listing(interp.instrs()));
+        return Ok(());
+    }
+    if let Some(target) = opt.emit {
+        let (instrs, config) = (interp.instrs(), interp.config());
+        if opt.emit_debug_info {
+            let offsets: Option<Vec<_>> = (0..instrs.len())
+                .map(|pc| interp.source_offset(pc))
+                .collect();
+            let (code, offsets) = match (&source, offsets) {
+                (Some(code), Some(offsets)) => (code, offsets),
+                _ => return Err(anyhow::anyhow!("--emit-debug-info needs brainfuck source")),
+            };
+            match target {
+                Target::C => print!("{}", emit::emit_c_debug(instrs, config, code, &offsets)),
+                Target::Rust => print!("{}", emit::emit_rust_debug(instrs, config, code, &offsets)),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "--emit-debug-info only applies to c and rust"
+                    ))
+                }
+            }
+            return Ok(());
+        }
+        match target {
+            Target::C => print!("{}", emit::emit_c(interp.instrs(), interp.config())),
+            Target::Rust => print!("{}", emit::emit_rust(interp.instrs(), interp.config())),
+            Target::Dot => print!("{}", emit::emit_dot(interp.instrs())),
+            Target::Wat => print!("{}", emit::emit_wat(interp.instrs(), interp.config())),
+        }
+        return Ok(());
+    }
+    if opt.debug && !opt.quiet {
+        match opt.debug_full {
+            true => eprintln!("{:?}", interp.instrs()),
+            false => eprint!("{}", format::summary(interp.instrs(), 10)),
+        }
+    }
+    if let Some(iterations) = opt.bench {
+        let input = read_input(&opt, split_input, separator.is_some())?;
+        let bench = bench(interp.instrs(), &input, interp.config(), iterations)
+            .map_err(|e| locate_error(e.into(), &interp, &opt, source))?;
+        eprintln!("{}", bench);
+        return Ok(());
+    }
+    if opt.verify_opt {
+        let code = source.unwrap_or_default();
+        let naive = parse_with(&code, interp.config().parse_options())?;
+        let input = read_input(&opt, split_input, separator.is_some())?;
+        let output = check_optimization(&naive, interp.instrs(), &input, interp.config())?;
+        let mut out = open_output(&opt)?;
+        out.write_all(&output)?;
+        out.flush()?;
+        return Ok(());
+    }
+    let input: Box<dyn Read> = match split_input {
+        Some(rest) => Box::new(io::Cursor::new(rest)),
+        None if separator.is_some() => Box::new(io::empty()),
+        None => open_input(&opt)?,
+    };
+    let input = wrap_input(&opt, input)?;
+    let mut output = match opt.memory_view && opt.output.is_none() {
+        // The output pane shows what would go to stdout
+        true => Box::new(io::sink()),
+        false => open_output(&opt)?,
+    };
+    if !opt.jit {
+        catch_interrupt(&mut interp);
+    }
+    let start = Instant::now();
+    let result = if opt.step || !opt.breakpoints.is_empty() {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let control = BufReader::new(tty.try_clone()?);
+        interp
+            .run_stepping(input, &mut output, control, tty)
+            .map_err(Error::from)
+    } else if let Some(path) = &opt.trace {
+        let trace = File::create(path)?;
+        interp
+            .run_traced(input, &mut output, trace)
+            .map_err(Error::from)
+    } else if opt.jit {
+        run_jit(&mut interp, input, &mut output)
+    } else if opt.memory_view {
+        let fps = opt.refresh_rate.unwrap_or(30.0);
+        run_memory_view(&mut interp, input, &mut output, fps)
+    } else {
+        interp
+            .run(input, &mut output)
+            .map(drop)
+            .map_err(Error::from)
+    };
+    // An interrupted run still reports the state it reached
+    let interrupted = match result {
+        Err(e) if matches!(e.downcast_ref(), Some(BfError::Interrupted { .. })) => Some(e),
+        result => {
+            result.map_err(|e| locate_error(e, &interp, &opt, source))?;
+            None
+        }
+    };
+    output.flush()?;
+    let run_time = start.elapsed();
+    if opt.json && !opt.quiet {
+        eprintln!(
+            "{}",
+            json_report(&opt, interp.stats(), parse_time, run_time)
+        );
+    }
+    if opt.time && !opt.json && !opt.quiet {
+        eprintln!("Executed in {:?}", run_time);
+    }
+    if opt.stats && !opt.json && !opt.quiet {
+        eprintln!("{}", interp.stats());
+    }
+    if opt.profile && !opt.json && !opt.quiet {
+        eprintln!("Hot loops:");
+        for (i, n) in interp.stats().hot_loops().into_iter().take(10) {
+            eprintln!("  instruction {:<8} {:>12} iterations", i, n);
+        }
+    }
+    if opt.dump_tape && !opt.quiet {
+        interp.dump_tape(io::stderr())?;
+    }
+    if let Some(path) = &opt.dump_tape_file {
+        fs::write(path, interp.tape_bytes())?;
+    }
+    if let Some(path) = &opt.save_state {
+        fs::write(path, interp.save_state())?;
+    }
+    if let Some(e) = interrupted {
+        return Err(e);
+    }
+    if opt.exit_code {
+        // Everything has been flushed, so nothing is lost by skipping destructors
+        process::exit(interp.cell_at(interp.origin()) as i32 & 0xff);
     }
-    run(&code, &opt)?;
     Ok(())
 }
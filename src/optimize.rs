@@ -0,0 +1,752 @@
+//! Optimization passes over parsed instructions
+
+use crate::resolve_brackets;
+use crate::BfOp::{self, *};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Which optimization passes to apply
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Run the instructions exactly as written
+    O0,
+    /// Cancel adjacent inverse instructions and fold runs and clear loops
+    O1,
+    /// Also fold scan and multiplication loops, runs of clears and pointer
+    /// offsets, remove loops that are never entered and skip the entry test
+    /// of loops that always are
+    O2,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            _ => Err(format!("Invalid optimization level: {}", s)),
+        }
+    }
+}
+
+/// Recomputes bracket offsets after instructions have been added or removed
+fn relink(instrs: &mut [BfOp]) {
+    resolve_brackets(instrs).expect("unbalanced brackets");
+}
+
+/// Instructions along with the index of the input instruction each came from
+pub(crate) type Mapped = (Vec<BfOp>, Vec<usize>);
+
+/// The output of a pass, recording where each instruction came from
+struct Output {
+    instrs: Vec<BfOp>,
+    origins: Vec<usize>,
+}
+
+impl Output {
+    fn with_capacity(n: usize) -> Self {
+        Output {
+            instrs: Vec::with_capacity(n),
+            origins: Vec::with_capacity(n),
+        }
+    }
+
+    fn push(&mut self, op: BfOp, origin: usize) {
+        self.instrs.push(op);
+        self.origins.push(origin);
+    }
+
+    fn finish(mut self) -> Mapped {
+        relink(&mut self.instrs);
+        (self.instrs, self.origins)
+    }
+}
+
+/// Whether `b` undoes `a` when run right after it
+fn cancels(a: &BfOp, b: &BfOp) -> bool {
+    match (a, b) {
+        (Plus, Minus) | (Minus, Plus) | (Gt, Lt) | (Lt, Gt) => true,
+        (Add(m), Add(n)) => m.wrapping_add(*n) == 0,
+        (Move(m), Move(n)) => m + n == 0,
+        _ => false,
+    }
+}
+
+/// Removes adjacent pairs of instructions that undo each other, like `+-`
+/// and `><`, along with `Add(0)` and `Move(0)`; brackets are never removed,
+/// so nothing cancels across one
+///
+/// ```
+/// use brainfuck::optimize::eliminate_cancellations;
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// assert_eq!(eliminate_cancellations(&parse(b"+-.").unwrap()), vec![Dot]);
+/// let instrs = parse(b"+[-]-").unwrap();
+/// assert_eq!(eliminate_cancellations(&instrs), instrs);
+///
+/// let instrs = parse(b">++<>+-.>+<-<>.").unwrap();
+/// let mut outputs = Vec::new();
+/// for program in vec![eliminate_cancellations(&instrs), instrs] {
+///     let mut output = Vec::new();
+///     let mut interp = Interpreter::from_ops(Config::default(), program).unwrap();
+///     interp.run(io::empty(), &mut output).unwrap();
+///     outputs.push(output);
+/// }
+/// assert_eq!(outputs[0], outputs[1]);
+/// ```
+pub fn eliminate_cancellations(instrs: &[BfOp]) -> Vec<BfOp> {
+    eliminate_cancellations_mapped(instrs).0
+}
+
+fn eliminate_cancellations_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    for (i, op) in instrs.iter().enumerate() {
+        match *op {
+            Add(0) | Move(0) => {}
+            // Cancelling a pair can expose another, as in `++--`
+            _ if out.instrs.last().is_some_and(|last| cancels(last, op)) => {
+                out.instrs.pop();
+                out.origins.pop();
+            }
+            _ => out.push(op.clone(), i),
+        }
+    }
+    out.finish()
+}
+
+/// Folds runs of `+`/`-` into `Add` and runs of `>`/`<` into `Move`
+///
+/// ```
+/// use brainfuck::optimize::fold_runs;
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// let code = "+".repeat(256) + &"+".repeat(65) + ".";
+/// assert_eq!(fold_runs(&parse(code.as_bytes()).unwrap()), [Add(321), Dot]);
+/// let mut out = Vec::new();
+/// Interpreter::new(Config::default())
+///     .eval(&code, io::empty(), &mut out)
+///     .unwrap();
+/// assert_eq!(out, b"A");
+/// ```
+pub fn fold_runs(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_runs_mapped(instrs).0
+}
+
+/// Like `fold_runs`, attributing each run to its last instruction
+fn fold_runs_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        match instrs[i] {
+            Plus | Minus => {
+                let mut n = 0i32;
+                while let Some(op @ (Plus | Minus)) = instrs.get(i) {
+                    n = n.wrapping_add(if let Plus = op { 1 } else { -1 });
+                    i += 1;
+                }
+                if n != 0 {
+                    out.push(Add(n), i - 1);
+                }
+            }
+            Gt | Lt => {
+                let mut n = 0isize;
+                while let Some(op @ (Gt | Lt)) = instrs.get(i) {
+                    n += if let Gt = op { 1 } else { -1 };
+                    i += 1;
+                }
+                if n != 0 {
+                    out.push(Move(n), i - 1);
+                }
+            }
+            ref op => {
+                out.push(op.clone(), i);
+                i += 1;
+            }
+        }
+    }
+    out.finish()
+}
+
+/// Replaces the clear loops `[-]` and `[+]` with `Clear`
+pub fn fold_clear_loops(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_clear_loops_mapped(instrs).0
+}
+
+fn fold_clear_loops_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        match instrs[i..] {
+            [LBracket(_), Plus | Minus | Add(1) | Add(-1), RBracket(_), ..] => {
+                out.push(Clear, i);
+                i += 3;
+            }
+            _ => {
+                out.push(instrs[i].clone(), i);
+                i += 1;
+            }
+        }
+    }
+    out.finish()
+}
+
+/// Replaces the scan loops `[>]` and `[<]` with `ScanRight` and `ScanLeft`
+pub fn fold_scan_loops(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_scan_loops_mapped(instrs).0
+}
+
+fn fold_scan_loops_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        match instrs[i..] {
+            [LBracket(_), Gt | Move(1), RBracket(_), ..] => {
+                out.push(ScanRight, i);
+                i += 3;
+            }
+            [LBracket(_), Lt | Move(-1), RBracket(_), ..] => {
+                out.push(ScanLeft, i);
+                i += 3;
+            }
+            _ => {
+                out.push(instrs[i].clone(), i);
+                i += 1;
+            }
+        }
+    }
+    out.finish()
+}
+
+/// Returns the change to each cell made by running a loop to completion,
+/// as a multiple of the current cell's starting value, if the body only
+/// adds and moves, changes the current cell by one and returns to where it
+/// started
+fn mul_loop_deltas(body: &[BfOp]) -> Option<BTreeMap<isize, i32>> {
+    let mut deltas = BTreeMap::new();
+    let mut offset = 0;
+    for op in body {
+        let delta = match *op {
+            Plus => 1,
+            Minus => -1,
+            Add(n) => n,
+            Gt => {
+                offset += 1;
+                continue;
+            }
+            Lt => {
+                offset -= 1;
+                continue;
+            }
+            Move(n) => {
+                offset += n;
+                continue;
+            }
+            _ => return None,
+        };
+        let d = deltas.entry(offset).or_insert(0i32);
+        *d = d.wrapping_add(delta);
+    }
+    if offset != 0 {
+        return None;
+    }
+    match deltas.remove(&0) {
+        Some(-1) => {}
+        // Counting up to zero takes the negated starting value iterations
+        Some(1) => deltas.values_mut().for_each(|d| *d = d.wrapping_neg()),
+        _ => return None,
+    }
+    deltas.retain(|_, d| *d != 0);
+    Some(deltas)
+}
+
+/// Replaces balanced copy and multiply loops like `[->++<]` with `MulAdd`
+/// ops followed by `Clear`, which alone replaces loops that only clear the
+/// current cell
+///
+/// Loops that only move or copy the cell, like `[->+<]`, become `MulAdd`s
+/// with a factor of 1, which are run without multiplying.
+///
+/// ```
+/// use brainfuck::optimize::{fold_mul_loops, fold_runs};
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// let instrs = fold_mul_loops(&fold_runs(&parse(b"[->+<]").unwrap()));
+/// assert_eq!(instrs, vec![MulAdd { offset: 1, factor: 1 }, Clear]);
+///
+/// for (code, tape) in vec![("+++[->+<]", vec![0, 3]), ("++[->+>+<<]", vec![0, 2, 2])] {
+///     let instrs = fold_mul_loops(&fold_runs(&parse(code.as_bytes()).unwrap()));
+///     let mut interp = Interpreter::from_ops(Config::default(), instrs).unwrap();
+///     interp.run(io::empty(), io::sink()).unwrap();
+///     assert_eq!(interp.tape(), &tape[..]);
+/// }
+/// ```
+pub fn fold_mul_loops(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_mul_loops_mapped(instrs).0
+}
+
+fn fold_mul_loops_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        if let LBracket(j) = instrs[i] {
+            if let Some(deltas) = mul_loop_deltas(&instrs[i + 1..j]) {
+                for (offset, factor) in deltas {
+                    out.push(MulAdd { offset, factor }, i);
+                }
+                out.push(Clear, i);
+                i = j + 1;
+                continue;
+            }
+        }
+        out.push(instrs[i].clone(), i);
+        i += 1;
+    }
+    out.finish()
+}
+
+/// Whether `instrs[start..end]` leaves the current cell zero and does
+/// nothing if it starts at zero, so a loop around it can be replaced by it
+fn runs_once(instrs: &[BfOp], start: usize, end: usize) -> bool {
+    match &instrs[start..end] {
+        [ScanRight] | [ScanLeft] => true,
+        [muls @ .., Clear] => muls.iter().all(|op| matches!(op, MulAdd { .. })),
+        [LBracket(k), ..] => *k + 1 == end,
+        _ => false,
+    }
+}
+
+/// Removes loops and other instructions that have no effect because the
+/// current cell is known to be zero, after a `Clear`, a scan, a `Reset` or
+/// the end of another loop, and unwraps loops whose body only runs once, like `[[-]]`
+///
+/// ```
+/// use brainfuck::optimize::{fold_clear_loops, remove_dead_loops};
+/// use brainfuck::{parse, BfOp::*};
+///
+/// let instrs = fold_clear_loops(&parse(b"+[-][>+<-][[-]]").unwrap());
+/// assert_eq!(remove_dead_loops(&instrs), vec![Plus, Clear]);
+/// ```
+pub fn remove_dead_loops(instrs: &[BfOp]) -> Vec<BfOp> {
+    remove_dead_loops_mapped(instrs).0
+}
+
+fn remove_dead_loops_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    // The `]`s of unwrapped loops
+    let mut unwrapped = Vec::new();
+    let mut zero = false;
+    let mut i = 0;
+    while i < instrs.len() {
+        let op = instrs[i].clone();
+        i += 1;
+        match op {
+            LBracket(j) if zero => {
+                i = j + 1;
+                continue;
+            }
+            LBracket(j) if runs_once(instrs, i, j) => {
+                unwrapped.push(j);
+                continue;
+            }
+            RBracket(_) if unwrapped.last() == Some(&(i - 1)) => {
+                unwrapped.pop();
+                continue;
+            }
+            MulAdd { .. } | Clear if zero => continue,
+            _ => {}
+        }
+        zero = match op {
+            Clear | ScanRight | ScanLeft | RBracket(_) | Reset | SetValue(0) => true,
+            ClearRange { from, len } => (from <= 0 && from + len as isize > 0) || zero,
+            MulAdd { offset, .. } | AddAt { offset, .. } if offset != 0 => zero,
+            Dot | WriteRepeat { .. } | Debug | PrintInt => zero,
+            _ => false,
+        };
+        out.push(op, i - 1);
+    }
+    out.finish()
+}
+
+/// Replaces runs of `Clear`s one cell apart, like `[-]>[-]>[-]`, with a
+/// `ClearRange` over the cells followed by a `Move` to the last of them
+///
+/// A `Clear` that ends a multiplication loop is left as it is.
+///
+/// ```
+/// use brainfuck::optimize::{fold_clear_loops, fold_clear_ranges, fold_runs};
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// let instrs = fold_clear_loops(&fold_runs(&parse(b"[-]>[-]>[-]").unwrap()));
+/// let instrs = fold_clear_ranges(&instrs);
+/// assert_eq!(instrs, vec![ClearRange { from: 0, len: 3 }, Move(2)]);
+///
+/// let mut instrs = instrs;
+/// instrs.insert(0, Move(1));
+/// let mut interp = Interpreter::from_ops(Config::default(), instrs).unwrap();
+/// interp.init_tape(&[5, 6, 7, 8, 9]).unwrap();
+/// interp.run(io::empty(), io::sink()).unwrap();
+/// assert_eq!(interp.tape(), &[5, 0, 0, 0, 9]);
+/// assert_eq!(interp.ptr(), 3);
+/// ```
+pub fn fold_clear_ranges(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_clear_ranges_mapped(instrs).0
+}
+
+/// Like `fold_clear_ranges`, attributing each range to its first `Clear`
+/// and the `Move` to its last one
+fn fold_clear_ranges_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        let after_mul = i > 0 && matches!(instrs[i - 1], MulAdd { .. });
+        let step = match instrs[i..] {
+            [Clear, Move(n @ (1 | -1)), Clear, ..] if !after_mul => n,
+            _ => {
+                out.push(instrs[i].clone(), i);
+                i += 1;
+                continue;
+            }
+        };
+        let start = i;
+        let mut len = 1;
+        while let [Clear, Move(n), Clear, ..] = instrs[i..] {
+            if n != step {
+                break;
+            }
+            len += 1;
+            i += 2;
+        }
+        let from = if step < 0 { 1 - len as isize } else { 0 };
+        out.push(ClearRange { from, len }, start);
+        out.push(Move(step * (len as isize - 1)), i);
+        i += 1;
+    }
+    out.finish()
+}
+
+/// Replaces each straight-line run of `Add`s and `Move`s with `AddAt`s at
+/// offsets from the starting cell and a single final `Move`
+///
+/// ```
+/// use brainfuck::optimize::{fold_offsets, fold_runs, OptLevel};
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// let code = b"+>++>+++<<->.";
+/// let instrs = fold_offsets(&fold_runs(&parse(code).unwrap()));
+/// assert_eq!(
+///     instrs,
+///     [AddAt { offset: 1, val: 2 }, AddAt { offset: 2, val: 3 }, Move(1), Dot]
+/// );
+///
+/// let mut naive = Interpreter::new(Config {
+///     opt_level: OptLevel::O0,
+///     ..Config::default()
+/// });
+/// let mut naive_out = Vec::new();
+/// naive.eval(code, io::empty(), &mut naive_out).unwrap();
+/// let mut folded = Interpreter::new(Config::default());
+/// folded.load_ops(instrs);
+/// let mut folded_out = Vec::new();
+/// folded.run(io::empty(), &mut folded_out).unwrap();
+/// assert_eq!(folded_out, naive_out);
+/// assert_eq!((folded.tape(), folded.ptr()), (naive.tape(), naive.ptr()));
+/// ```
+pub fn fold_offsets(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_offsets_mapped(instrs).0
+}
+
+/// Like `fold_offsets`, attributing each `AddAt` to the first `Add` of its
+/// cell and the `Move` to the last instruction of the run
+fn fold_offsets_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        if !matches!(instrs[i], Add(_) | Move(_)) {
+            out.push(instrs[i].clone(), i);
+            i += 1;
+            continue;
+        }
+        let mut offset = 0isize;
+        let mut deltas = BTreeMap::new();
+        while let Some(op @ (Add(_) | Move(_))) = instrs.get(i) {
+            match *op {
+                Add(n) => {
+                    let (delta, _) = deltas.entry(offset).or_insert((0i32, i));
+                    *delta = delta.wrapping_add(n);
+                }
+                Move(n) => offset += n,
+                _ => unreachable!(),
+            }
+            i += 1;
+        }
+        for (at, (val, origin)) in deltas {
+            match (at, val) {
+                (_, 0) => {}
+                (0, val) => out.push(Add(val), origin),
+                (offset, val) => out.push(AddAt { offset, val }, origin),
+            }
+        }
+        if offset != 0 {
+            out.push(Move(offset), i - 1);
+        }
+    }
+    out.finish()
+}
+
+/// Replaces `[` with `LoopStart` where the current cell is known to be
+/// nonzero: after it has been zeroed and then changed by an amount that
+/// can't wrap back to zero in any cell size
+///
+/// The tape may be preloaded, so cells aren't assumed to start at zero.
+///
+/// ```
+/// use brainfuck::optimize::remove_loop_guards;
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// let instrs = parse(b"+[-]").unwrap();
+/// assert_eq!(remove_loop_guards(&instrs), instrs);
+///
+/// // The second loop always runs, after the first leaves a zero
+/// let instrs = remove_loop_guards(&parse(b"+[-]+[-.]").unwrap());
+/// assert_eq!(instrs[5], LoopStart(8));
+/// let mut output = Vec::new();
+/// Interpreter::from_ops(Config::default(), instrs)
+///     .unwrap()
+///     .run(io::empty(), &mut output)
+///     .unwrap();
+/// assert_eq!(output, [0]);
+/// ```
+pub fn remove_loop_guards(instrs: &[BfOp]) -> Vec<BfOp> {
+    remove_loop_guards_mapped(instrs).0
+}
+
+fn remove_loop_guards_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    // The current cell modulo 256, if known, which is nonzero in every cell
+    // size if it is nonzero here
+    let mut known: Option<u8> = None;
+    for (i, op) in instrs.iter().enumerate() {
+        let op = match *op {
+            LBracket(j) if known.is_some_and(|v| v != 0) => LoopStart(j),
+            ref op => op.clone(),
+        };
+        known = match op {
+            Clear | ScanRight | ScanLeft | RBracket(_) | Reset => Some(0),
+            SetValue(n) => Some(n as u8),
+            ClearRange { from, len } if from <= 0 && from + len as isize > 0 => Some(0),
+            Plus => known.map(|v| v.wrapping_add(1)),
+            Minus => known.map(|v| v.wrapping_sub(1)),
+            Add(n) | AddAt { offset: 0, val: n } => known.map(|v| v.wrapping_add(n as u8)),
+            MulAdd { .. } | AddAt { .. } | ClearRange { .. } => known,
+            Dot | WriteRepeat { .. } | Debug | PrintInt | Comment(_) => known,
+            _ => None,
+        };
+        out.push(op, i);
+    }
+    out.finish()
+}
+
+/// Replaces runs of `.` with `WriteRepeat`, which writes the run's bytes in
+/// one go; only adjacent `.`s are folded, so nothing between them can
+/// change the cell or move the pointer
+///
+/// This isn't part of any level, since the program's output no longer
+/// reaches the writer one `.` at a time.
+///
+/// ```
+/// use brainfuck::optimize::fold_writes;
+/// use brainfuck::{parse, BfOp::*, Config, Interpreter};
+/// use std::io;
+///
+/// let instrs = fold_writes(&parse(b"+++.....").unwrap());
+/// assert_eq!(instrs[3..], [WriteRepeat { count: 5 }]);
+/// let mut interp = Interpreter::new(Config::default());
+/// interp.load_ops(instrs);
+/// let mut output = Vec::new();
+/// interp.run(io::empty(), &mut output).unwrap();
+/// assert_eq!(output, [3; 5]);
+///
+/// let split = fold_writes(&parse(b"..+..").unwrap());
+/// assert_eq!(split, vec![WriteRepeat { count: 2 }, Plus, WriteRepeat { count: 2 }]);
+/// ```
+pub fn fold_writes(instrs: &[BfOp]) -> Vec<BfOp> {
+    fold_writes_mapped(instrs).0
+}
+
+fn fold_writes_mapped(instrs: &[BfOp]) -> Mapped {
+    let mut out = Output::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        let count = instrs[i..]
+            .iter()
+            .take_while(|op| matches!(op, Dot))
+            .count();
+        match count {
+            0 | 1 => {
+                out.push(instrs[i].clone(), i);
+                i += 1;
+            }
+            count => {
+                out.push(WriteRepeat { count }, i);
+                i += count;
+            }
+        }
+    }
+    out.finish()
+}
+
+/// Like `fold_writes`, keeping each instruction's source offset
+pub(crate) fn fold_writes_offsets(mapped: Mapped) -> Mapped {
+    apply(fold_writes_mapped, mapped)
+}
+
+/// Applies the optimization passes enabled at `level`, returning the
+/// instructions with their bracket offsets recomputed and, unless `level`
+/// is `O0`, any comments removed
+///
+/// Panics if the brackets are unbalanced.
+///
+/// ```
+/// use brainfuck::{optimize, parse, Config, Interpreter, OptLevel};
+/// use std::io;
+///
+/// let instrs = parse(b"+++[->++<]>>+").unwrap();
+/// let optimized = optimize(instrs.clone(), OptLevel::O2);
+/// assert!(optimized.len() < instrs.len());
+///
+/// let mut tapes = Vec::new();
+/// for program in vec![instrs, optimized] {
+///     let mut interp = Interpreter::new(Config::default());
+///     interp.load_ops(program);
+///     interp.run(io::empty(), io::sink()).unwrap();
+///     tapes.push(interp.tape().to_vec());
+/// }
+/// assert_eq!(tapes[0], tapes[1]);
+/// ```
+pub fn optimize(instrs: Vec<BfOp>, level: OptLevel) -> Vec<BfOp> {
+    let offsets = (0..instrs.len()).collect();
+    optimize_mapped(instrs, offsets, level).0
+}
+
+/// Runs a pass over instructions paired with offsets, keeping the offset of
+/// the instruction each output one came from
+fn apply(pass: fn(&[BfOp]) -> Mapped, (instrs, offsets): Mapped) -> Mapped {
+    let (out, origins) = pass(&instrs);
+    let offsets = origins.iter().map(|&i| offsets[i]).collect();
+    (out, offsets)
+}
+
+/// Like `optimize`, also returning the entry of `offsets`, such as a source
+/// byte offset, for the instruction each optimized one came from
+pub(crate) fn optimize_mapped(instrs: Vec<BfOp>, offsets: Vec<usize>, level: OptLevel) -> Mapped {
+    let mut mapped = (instrs, offsets);
+    if level >= OptLevel::O1 {
+        mapped = mapped
+            .0
+            .into_iter()
+            .zip(mapped.1)
+            .filter(|(op, _)| !matches!(op, Comment(_)))
+            .unzip();
+        mapped = apply(eliminate_cancellations_mapped, mapped);
+        mapped = apply(fold_clear_loops_mapped, apply(fold_runs_mapped, mapped));
+    }
+    if level >= OptLevel::O2 {
+        mapped = apply(fold_mul_loops_mapped, apply(fold_scan_loops_mapped, mapped));
+        mapped = apply(
+            fold_clear_ranges_mapped,
+            apply(remove_dead_loops_mapped, mapped),
+        );
+        mapped = apply(
+            remove_loop_guards_mapped,
+            apply(fold_offsets_mapped, mapped),
+        );
+    }
+    if level == OptLevel::O0 {
+        relink(&mut mapped.0);
+    }
+    mapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Config, Interpreter};
+    use std::io;
+
+    /// Runs instructions on a fresh interpreter, returning it and the output
+    fn run_ops(instrs: Vec<BfOp>) -> (Interpreter, Vec<u8>) {
+        let mut interp = Interpreter::new(Config::default());
+        interp.instrs = instrs;
+        let mut out = Vec::new();
+        interp.run(io::empty(), &mut out).unwrap();
+        (interp, out)
+    }
+
+    /// Runs `code` at `level`, returning the interpreter and the output
+    fn run(code: &str, level: OptLevel) -> (Interpreter, Vec<u8>) {
+        let mut interp = Interpreter::new(Config {
+            opt_level: level,
+            ..Config::default()
+        });
+        let mut out = Vec::new();
+        interp.eval(code, io::empty(), &mut out).unwrap();
+        (interp, out)
+    }
+
+    #[test]
+    fn clear_loops_run_like_the_loops() {
+        let code = b"+++++[-].>++[+].+++[>++[-]<-]>.<+[+-[-]]>++++[--].";
+        let instrs = fold_runs(&parse(code).unwrap());
+        let cleared = fold_clear_loops(&instrs);
+        assert!(cleared.iter().filter(|&op| *op == Clear).count() >= 3);
+        assert_eq!(run_ops(cleared).1, run_ops(instrs).1);
+    }
+
+    #[test]
+    fn scan_loops_match_naive_execution() {
+        let code = ">+>+>+>+>+<<<<[>]>+<<<+[<]";
+        let instrs = optimize(parse(code.as_bytes()).unwrap(), OptLevel::O2);
+        assert!(instrs.contains(&ScanRight) && instrs.contains(&ScanLeft));
+        let (naive, _) = run(code, OptLevel::O0);
+        let (optimized, _) = run(code, OptLevel::O2);
+        assert_eq!(optimized.ptr(), naive.ptr());
+        assert_eq!(optimized.tape(), naive.tape());
+    }
+
+    #[test]
+    fn multiply_loop_doubles_the_cell() {
+        let instrs = optimize(parse(b"+++++[->++<]").unwrap(), OptLevel::O2);
+        assert!(instrs.contains(&MulAdd {
+            offset: 1,
+            factor: 2
+        }));
+        let (interp, _) = run("+++++[->++<]", OptLevel::O2);
+        assert_eq!(interp.tape(), [0, 10]);
+
+        // The output inside the loop keeps it from being folded
+        let code = "+++[->++<.]";
+        let instrs = optimize(parse(code.as_bytes()).unwrap(), OptLevel::O2);
+        assert!(!instrs.iter().any(|op| matches!(op, MulAdd { .. })));
+        let (interp, out) = run(code, OptLevel::O2);
+        assert_eq!((interp.tape(), &out[..]), (&[0, 6][..], &[2, 1, 0][..]));
+    }
+
+    #[test]
+    fn fibonacci_is_the_same_at_every_level() {
+        // Prints the first ten Fibonacci numbers as bytes, moving them with
+        // loops the optimizer folds
+        let code = "++++++++++>>+<<[>>.[->+>+<<]<[->>+<<]>>>[-<<<+>>>]<[-<+>]<<<-]";
+        let outputs = [OptLevel::O0, OptLevel::O1, OptLevel::O2].map(|level| run(code, level).1);
+        assert_eq!(outputs[0], [1, 1, 2, 3, 5, 8, 13, 21, 34, 55]);
+        assert_eq!(outputs[1], outputs[0]);
+        assert_eq!(outputs[2], outputs[0]);
+    }
+}
@@ -0,0 +1,129 @@
+//! Expansion of `!include path` directives
+//!
+//! A line whose first non-blank characters are `!include` is replaced by the
+//! contents of the named file, which is itself preprocessed. Paths are
+//! resolved relative to the directory of the including file.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const DIRECTIVE: &[u8] = b"!include";
+
+#[derive(Debug, Error)]
+pub enum PreprocessError {
+    #[error("Cannot include {}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Include cycle: {} includes itself", .0.display())]
+    Cycle(PathBuf),
+}
+
+/// Expands the includes in `code`, which was read from `path`, or from stdin
+/// or the command line if `None`
+pub fn preprocess(code: &[u8], path: Option<&Path>) -> Result<Vec<u8>, PreprocessError> {
+    let mut stack = Vec::new();
+    let dir = match path {
+        Some(path) => {
+            stack.push(canonicalize(path)?);
+            path.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+        }
+        None => PathBuf::new(),
+    };
+    let mut out = Vec::new();
+    expand(code, &dir, &mut stack, &mut out)?;
+    Ok(out)
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, PreprocessError> {
+    path.canonicalize().map_err(|source| PreprocessError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// The included path if `line` is a directive
+fn directive(line: &[u8]) -> Option<PathBuf> {
+    let start = line.iter().position(|c| !c.is_ascii_whitespace())?;
+    let rest = line[start..].strip_prefix(DIRECTIVE)?;
+    if !rest.first().is_some_and(u8::is_ascii_whitespace) {
+        return None;
+    }
+    let name = String::from_utf8_lossy(rest);
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(name))
+}
+
+/// Appends `code` to `out` with its includes expanded, `stack` holding the
+/// canonical paths of the files currently being expanded
+fn expand(
+    code: &[u8],
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    out: &mut Vec<u8>,
+) -> Result<(), PreprocessError> {
+    for line in code.split_inclusive(|&c| c == b'\n') {
+        let name = match directive(line) {
+            Some(name) => name,
+            None => {
+                out.extend_from_slice(line);
+                continue;
+            }
+        };
+        let path = dir.join(name);
+        let canonical = canonicalize(&path)?;
+        if stack.contains(&canonical) {
+            return Err(PreprocessError::Cycle(path));
+        }
+        let included = std::fs::read(&path).map_err(|source| PreprocessError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        stack.push(canonical);
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        expand(&included, parent, stack, out)?;
+        stack.pop();
+        if line.ends_with(b"\n") && !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A fresh directory in the temp directory unique to this test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bf-pre-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn include_is_expanded_in_place() {
+        let dir = temp_dir("include");
+        fs::write(dir.join("lib.bf"), b"++\n").unwrap();
+        let main = dir.join("main.bf");
+        let code = b"+\n!include lib.bf\n.\n";
+        fs::write(&main, code).unwrap();
+        let result = preprocess(code, Some(&main));
+        fs::remove_dir_all(dir).unwrap();
+        assert_eq!(result.unwrap(), b"+\n++\n.\n");
+    }
+
+    #[test]
+    fn self_include_is_a_cycle() {
+        let dir = temp_dir("cycle");
+        let main = dir.join("main.bf");
+        let code = b"+\n!include main.bf\n";
+        fs::write(&main, code).unwrap();
+        let result = preprocess(code, Some(&main));
+        fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(result, Err(PreprocessError::Cycle(_))));
+    }
+}
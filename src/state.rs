@@ -0,0 +1,289 @@
+//! Saving the interpreter's memory so a later run can pick up where it left
+//! off
+//!
+//! A state file starts with the magic bytes `BFS` and a version byte,
+//! followed by the settings the tape was built with: the cell width in bits,
+//! a byte of flags (1 for pointer wrapping, 2 for a bidirectional tape and
+//! 4 for having reached EOF) and the tape size as a `u64`, 0 meaning
+//! unbounded. Then come the tape length, the origin, the pointer and the
+//! state of the generator for `?` as `u64`s, and the number of stored cells
+//! as a `u64` with each cell's index as a `u64` and value as a `u32`. The
+//! stored cells are the nonzero ones in order, followed by the last cell of
+//! the tape if it is zero, so the tape is never longer than the cells in the
+//! file imply. All numbers are little-endian.
+
+use crate::tape::Tape;
+use crate::{BfError, Config, Interpreter};
+use std::convert::TryInto;
+
+const MAGIC: &[u8] = b"BFS";
+const VERSION: u8 = 1;
+
+const WRAP_POINTER: u8 = 1;
+const BIDIRECTIONAL: u8 = 2;
+const AT_EOF: u8 = 4;
+
+/// Reads fixed-size fields from the front of a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], BfError> {
+        if self.0.len() < N {
+            return Err(BfError::InvalidState("unexpected end of file"));
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(head.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, BfError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BfError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, BfError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, BfError> {
+        self.u64()?
+            .try_into()
+            .map_err(|_| BfError::InvalidState("index too large"))
+    }
+}
+
+/// Fails unless a setting in the state, described as `saved`, matches the
+/// interpreter's config
+fn check(saved: &str, current: &str) -> Result<(), BfError> {
+    match saved == current {
+        true => Ok(()),
+        false => Err(BfError::IncompatibleState(format!(
+            "saved with {}, but this run uses {}",
+            saved, current
+        ))),
+    }
+}
+
+/// Describes the settings in a byte of flags for `IncompatibleState`
+fn describe_flags(flags: u8) -> [&'static str; 2] {
+    let wrap = match flags & WRAP_POINTER {
+        0 => "no pointer wrapping",
+        _ => "pointer wrapping",
+    };
+    let bidirectional = match flags & BIDIRECTIONAL {
+        0 => "a one-sided tape",
+        _ => "a bidirectional tape",
+    };
+    [wrap, bidirectional]
+}
+
+/// Describes a tape size for `IncompatibleState`, 0 meaning unbounded
+fn describe_size(size: u64) -> String {
+    match size {
+        0 => "an unbounded tape".to_string(),
+        n => format!("a tape of {} cells", n),
+    }
+}
+
+/// The flags describing `config`, without `AT_EOF`
+fn config_flags(config: &Config) -> u8 {
+    let mut flags = 0;
+    if config.wrap_pointer {
+        flags |= WRAP_POINTER;
+    }
+    if config.bidirectional {
+        flags |= BIDIRECTIONAL;
+    }
+    flags
+}
+
+impl Interpreter {
+    /// Serializes the tape, pointer and the config they depend on, which
+    /// `load_state` restores
+    pub fn save_state(&self) -> Vec<u8> {
+        let config = &self.config;
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.push(config.cell_size.bits() as u8);
+        let mut flags = config_flags(config);
+        if self.at_eof {
+            flags |= AT_EOF;
+        }
+        out.push(flags);
+        out.extend(&(config.tape_size.unwrap_or(0) as u64).to_le_bytes());
+        for n in [self.tape.len(), self.origin, self.ptr] {
+            out.extend(&(n as u64).to_le_bytes());
+        }
+        out.extend(&self.rng.to_le_bytes());
+        let mut cells = self.tape.nonzero();
+        let last = self.tape.len() - 1;
+        if cells.last().is_none_or(|&(i, _)| i != last) {
+            cells.push((last, 0));
+        }
+        out.extend(&(cells.len() as u64).to_le_bytes());
+        for (i, value) in cells {
+            out.extend(&(i as u64).to_le_bytes());
+            out.extend(&value.to_le_bytes());
+        }
+        out
+    }
+
+    /// Replaces the tape and pointer with ones saved by `save_state`,
+    /// failing if they were saved with a different cell size, pointer
+    /// wrapping, bidirectional or tape size setting
+    ///
+    /// ```
+    /// use brainfuck::{BfError, CellSize, Config, Interpreter};
+    /// use std::io;
+    ///
+    /// let config = Config {
+    ///     cell_size: CellSize::U16,
+    ///     ..Config::default()
+    /// };
+    /// let mut first = Interpreter::new(config.clone());
+    /// first.eval("+++++[>+++++++++++++<-]>", io::empty(), io::sink()).unwrap();
+    /// let state = first.save_state();
+    ///
+    /// let mut second = Interpreter::new(config);
+    /// second.load_state(&state).unwrap();
+    /// let mut out = Vec::new();
+    /// second.eval(".<+.", io::empty(), &mut out).unwrap();
+    /// assert_eq!(out, [65, 1]);
+    ///
+    /// let mut other = Interpreter::new(Config::default());
+    /// let err = other.load_state(&state).unwrap_err();
+    /// assert!(matches!(err, BfError::IncompatibleState(_)));
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "Incompatible saved state: saved with 16-bit cells, but this run uses 8-bit cells"
+    /// );
+    /// ```
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), BfError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(BfError::InvalidState("missing header"));
+        }
+        let mut r = Reader(&bytes[MAGIC.len()..]);
+        if r.u8()? != VERSION {
+            return Err(BfError::InvalidState("unsupported version"));
+        }
+        let config = &self.config;
+        let bits = r.u8()?;
+        check(
+            &format!("{}-bit cells", bits),
+            &format!("{}-bit cells", config.cell_size.bits()),
+        )?;
+        let flags = r.u8()?;
+        for (saved, current) in describe_flags(flags)
+            .iter()
+            .zip(describe_flags(config_flags(config)))
+        {
+            check(saved, current)?;
+        }
+        check(
+            &describe_size(r.u64()?),
+            &describe_size(config.tape_size.unwrap_or(0) as u64),
+        )?;
+        let (len, origin, ptr) = (r.usize()?, r.usize()?, r.usize()?);
+        let rng = r.u64()?;
+        if ptr >= len || origin >= len {
+            return Err(BfError::InvalidState("pointer outside the tape"));
+        }
+        let count = r.usize()?;
+        // Each cell takes 12 bytes, so this can't reserve more than the file
+        let mut cells = Vec::with_capacity(count.min(r.0.len() / 12));
+        for _ in 0..count {
+            let (i, value) = (r.usize()?, r.u32()?);
+            if i >= len {
+                return Err(BfError::InvalidState("cell outside the tape"));
+            }
+            if value > config.cell_size.mask() {
+                return Err(BfError::InvalidState("cell value too large"));
+            }
+            cells.push((i, value));
+        }
+        if !r.0.is_empty() {
+            return Err(BfError::InvalidState("trailing data"));
+        }
+        // The length comes from the file, so check it against what the file
+        // holds before allocating it
+        let stored_len = cells.iter().map(|&(i, _)| i + 1).max().unwrap_or(0);
+        if config.tape_size.map_or(len > stored_len, |n| len > n) {
+            return Err(BfError::InvalidState("tape too large"));
+        }
+        let mut tape =
+            Tape::try_new(len, config.sparse).ok_or(BfError::InvalidState("tape too large"))?;
+        for (i, value) in cells {
+            tape[i] = value;
+        }
+        self.tape = tape;
+        self.origin = origin;
+        self.ptr = ptr;
+        self.max_ptr = ptr;
+        self.rng = rng;
+        self.at_eof = flags & AT_EOF != 0;
+        self.pc = 0;
+        self.journal.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BfError, Config, Interpreter};
+    use std::io;
+
+    /// A state file for the default config with the given tape length and
+    /// stored cells, and the pointer at cell 0
+    fn state(len: u64, cells: &[(u64, u32)]) -> Vec<u8> {
+        let mut out = b"BFS\x01\x08\x00".to_vec();
+        for n in [0, len, 0, 0, 0, cells.len() as u64] {
+            out.extend(&n.to_le_bytes());
+        }
+        for &(i, value) in cells {
+            out.extend(&i.to_le_bytes());
+            out.extend(&value.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn huge_tape_is_rejected() {
+        let mut interp = Interpreter::new(Config::default());
+        let huge = 1 << 60;
+        for cells in [&[][..], &[(0, 1)], &[(huge - 1, 1)]] {
+            let result = interp.load_state(&state(huge, cells));
+            assert!(matches!(
+                result,
+                Err(BfError::InvalidState("tape too large"))
+            ));
+        }
+    }
+
+    #[test]
+    fn tape_longer_than_its_size_is_rejected() {
+        let config = Config {
+            tape_size: Some(4),
+            ..Config::default()
+        };
+        let mut state = state(5, &[(4, 0)]);
+        state[6..14].copy_from_slice(&4u64.to_le_bytes());
+        let result = Interpreter::new(config).load_state(&state);
+        assert!(matches!(
+            result,
+            Err(BfError::InvalidState("tape too large"))
+        ));
+    }
+
+    #[test]
+    fn trailing_zero_cells_round_trip() {
+        let mut first = Interpreter::new(Config::default());
+        first.eval("+>>", io::empty(), io::sink()).unwrap();
+        let mut second = Interpreter::new(Config::default());
+        second.load_state(&first.save_state()).unwrap();
+        assert_eq!(second.tape(), [1, 0, 0]);
+        assert_eq!(second.ptr(), 2);
+    }
+}
@@ -0,0 +1,146 @@
+//! Storage for the cells of the tape
+
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut, Range};
+
+/// The cells of the tape, which reads as zero past the highest cell touched
+#[derive(Debug, Clone)]
+pub(crate) enum Tape {
+    /// Every cell up to the highest one touched
+    Dense(Vec<u32>),
+    /// Only the cells that have been written, so memory use doesn't depend
+    /// on how far apart they are
+    Sparse {
+        cells: HashMap<usize, u32>,
+        /// One past the highest cell touched
+        len: usize,
+    },
+}
+
+impl Tape {
+    pub fn new(len: usize, sparse: bool) -> Self {
+        match sparse {
+            true => Tape::Sparse {
+                cells: HashMap::new(),
+                len,
+            },
+            false => Tape::Dense(vec![0; len]),
+        }
+    }
+
+    /// Like `new`, but `None` if a dense tape of `len` cells can't be
+    /// allocated
+    pub fn try_new(len: usize, sparse: bool) -> Option<Self> {
+        if sparse {
+            return Some(Tape::new(len, true));
+        }
+        let mut cells = Vec::new();
+        cells.try_reserve_exact(len).ok()?;
+        cells.resize(len, 0);
+        Some(Tape::Dense(cells))
+    }
+
+    /// One past the highest cell touched
+    pub fn len(&self) -> usize {
+        match self {
+            Tape::Dense(cells) => cells.len(),
+            Tape::Sparse { len, .. } => *len,
+        }
+    }
+
+    /// The number of cells held in memory
+    pub fn stored(&self) -> usize {
+        match self {
+            Tape::Dense(cells) => cells.len(),
+            Tape::Sparse { cells, .. } => cells.len(),
+        }
+    }
+
+    /// Extends the tape to at least `new_len` cells
+    pub fn grow(&mut self, new_len: usize) {
+        match self {
+            Tape::Dense(cells) if new_len > cells.len() => cells.resize(new_len, 0),
+            Tape::Dense(_) => {}
+            Tape::Sparse { len, .. } => *len = new_len.max(*len),
+        }
+    }
+
+    /// Adds `n` zero cells before the first, shifting every cell right
+    pub fn grow_front(&mut self, n: usize) {
+        match self {
+            Tape::Dense(cells) => {
+                cells.splice(0..0, std::iter::repeat_n(0, n));
+            }
+            Tape::Sparse { cells, len } => {
+                *cells = cells.drain().map(|(i, c)| (i + n, c)).collect();
+                *len += n;
+            }
+        }
+    }
+
+    /// The value of a cell, which is zero past the end
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            Tape::Dense(cells) => cells.get(i).copied().unwrap_or(0),
+            Tape::Sparse { cells, .. } => cells.get(&i).copied().unwrap_or(0),
+        }
+    }
+
+    /// The index and value of each nonzero cell, in order
+    pub fn nonzero(&self) -> Vec<(usize, u32)> {
+        let mut cells: Vec<_> = match self {
+            Tape::Dense(cells) => cells.iter().copied().enumerate().collect(),
+            Tape::Sparse { cells, .. } => cells.iter().map(|(&i, &c)| (i, c)).collect(),
+        };
+        cells.retain(|&(_, c)| c != 0);
+        cells.sort_unstable();
+        cells
+    }
+
+    /// Sets every cell to zero
+    pub fn clear(&mut self) {
+        match self {
+            Tape::Dense(cells) => cells.iter_mut().for_each(|c| *c = 0),
+            Tape::Sparse { cells, .. } => cells.clear(),
+        }
+    }
+
+    /// Sets the cells in `range` to zero
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        match self {
+            Tape::Dense(cells) => cells[range].iter_mut().for_each(|c| *c = 0),
+            Tape::Sparse { cells, .. } => range.for_each(|i| {
+                cells.remove(&i);
+            }),
+        }
+    }
+
+    /// The cells of a dense tape
+    pub fn as_slice(&self) -> &[u32] {
+        match self {
+            Tape::Dense(cells) => cells,
+            Tape::Sparse { .. } => &[],
+        }
+    }
+}
+
+impl Index<usize> for Tape {
+    type Output = u32;
+
+    fn index(&self, i: usize) -> &u32 {
+        match self {
+            Tape::Dense(cells) => &cells[i],
+            Tape::Sparse { cells, .. } => cells.get(&i).unwrap_or(&0),
+        }
+    }
+}
+
+impl IndexMut<usize> for Tape {
+    /// A sparse tape stores the cell from now on
+    fn index_mut(&mut self, i: usize) -> &mut u32 {
+        match self {
+            Tape::Dense(cells) => &mut cells[i],
+            Tape::Sparse { cells, .. } => cells.entry(i).or_insert(0),
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! A live view of the tape in the terminal while a program runs, drawn with
+//! ANSI escape codes
+
+use crate::{BfError, Interpreter};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Steps between checks of the clock, which is slow to read every step
+const CLOCK_INTERVAL: u64 = 1024;
+/// Cells shown on each side of the pointer
+const RADIUS: usize = 7;
+/// Bytes of recent program output kept for the output pane
+const PANE_BYTES: usize = 1024;
+/// Lines of program output shown under the tape
+const PANE_LINES: usize = 4;
+
+/// Draws frames showing the cells around the pointer, with the current
+/// cell highlighted, and the end of the program's output
+pub struct MemoryView<W> {
+    out: W,
+    /// The shortest time between frames
+    interval: Duration,
+    last_frame: Option<Instant>,
+    frames: usize,
+}
+
+impl<W: Write> MemoryView<W> {
+    /// Creates a view that draws to `out` at most `fps` times a second
+    pub fn new(out: W, fps: f64) -> Self {
+        MemoryView {
+            out,
+            interval: Duration::from_secs_f64(1.0 / fps),
+            last_frame: None,
+            frames: 0,
+        }
+    }
+
+    /// The number of frames drawn so far
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Whether the next frame is due
+    fn due(&self) -> bool {
+        self.last_frame
+            .is_none_or(|last| last.elapsed() >= self.interval)
+    }
+
+    /// Redraws the screen with the interpreter's tape and the end of the
+    /// output written so far
+    pub fn draw(&mut self, interp: &Interpreter, output: &[u8]) -> io::Result<()> {
+        let ptr = interp.ptr();
+        let cells = ptr.saturating_sub(RADIUS)..ptr + RADIUS + 1;
+        // Move to the top left and clear the screen
+        let mut frame = String::from("\x1b[H\x1b[J");
+        frame.push_str(&format!("pc {}  ptr {}\n", interp.pc(), ptr));
+        for i in cells.clone() {
+            frame.push_str(&format!("{:>6}", i));
+        }
+        frame.push('\n');
+        for i in cells {
+            let cell = format!("{:>6}", interp.cell_at(i));
+            match i == ptr {
+                // Reverse video
+                true => frame.push_str(&format!("\x1b[7m{}\x1b[0m", cell)),
+                false => frame.push_str(&cell),
+            }
+        }
+        frame.push_str("\n\nOutput:\n");
+        let text: String = String::from_utf8_lossy(output)
+            .chars()
+            .map(|c| match c {
+                '\n' => c,
+                c if c.is_control() => '.',
+                c => c,
+            })
+            .collect();
+        let lines: Vec<_> = text.lines().collect();
+        for line in &lines[lines.len().saturating_sub(PANE_LINES)..] {
+            frame.push_str(line);
+            frame.push('\n');
+        }
+        self.out.write_all(frame.as_bytes())?;
+        self.out.flush()?;
+        self.last_frame = Some(Instant::now());
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Passes program output through, keeping its end for the output pane
+struct Pane<W> {
+    inner: W,
+    tail: Vec<u8>,
+}
+
+impl<W: Write> Write for Pane<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.tail.extend(&buf[..n]);
+        if self.tail.len() > PANE_BYTES {
+            self.tail.drain(..self.tail.len() - PANE_BYTES);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Runs the loaded program like `Interpreter::run`, redrawing `view` as it
+/// goes and once more when the program stops
+///
+/// ```
+/// use brainfuck::view::{self, MemoryView};
+/// use brainfuck::{Config, Interpreter};
+/// use std::io;
+///
+/// let mut interp = Interpreter::new(Config::default());
+/// interp.load("++++++++[>++++++++<-]>+.").unwrap();
+/// let (mut screen, mut output) = (Vec::new(), Vec::new());
+/// let mut view = MemoryView::new(&mut screen, 30.0);
+/// view::run(&mut interp, io::empty(), &mut output, &mut view).unwrap();
+/// assert!(view.frames() >= 1);
+/// assert_eq!(output, b"A");
+/// let screen = String::from_utf8(screen).unwrap();
+/// assert!(screen.contains("\x1b[7m    65\x1b[0m"));
+/// ```
+pub fn run<R: Read, W: Write, V: Write>(
+    interp: &mut Interpreter,
+    input: R,
+    output: W,
+    view: &mut MemoryView<V>,
+) -> Result<(), BfError> {
+    let output = Pane {
+        inner: output,
+        tail: Vec::new(),
+    };
+    let mut steps = interp.steps(input, output);
+    let mut count = 0u64;
+    let mut result = Ok(());
+    while let Some(step) = steps.next() {
+        if let Err(e) = step {
+            result = Err(e);
+            break;
+        }
+        count += 1;
+        if count.is_multiple_of(CLOCK_INTERVAL) && view.due() {
+            view.draw(steps.interpreter(), &steps.output().tail)?;
+        }
+    }
+    view.draw(steps.interpreter(), &steps.output().tail)?;
+    result
+}
@@ -0,0 +1,230 @@
+//! Runs the command-line interface on small programs
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+
+/// Prints "Hi"
+const HI: &str = "++++++++[>+++++++++<-]>.+++++++++++++++++++++++++++++++++.";
+
+/// Runs the binary with `args`, feeding `input` to its stdin
+fn run(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The program may finish without reading all of its input
+    match child.stdin.take().unwrap().write_all(input) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => panic!("{}", e),
+        _ => (),
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// A path in the temp directory unique to this test process
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bf-cli-{}-{}", process::id(), name))
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn tape_size_bounds_the_tape() {
+    let code = format!("{}+", ">".repeat(69999));
+    let output = run(&["--tape-size", "100000", "-c", &code], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(&["--tape-size", "65536", "-c", &code], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Pointer out of bounds"));
+    let output = run(&["--tape-size", "0", "-c", "+"], b"");
+    assert!(stderr(&output).contains("tape size must be at least 1"));
+}
+
+#[test]
+fn output_goes_to_a_file() {
+    let path = temp_path("output");
+    let output = run(&["-o", path.to_str().unwrap(), "-c", HI], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"");
+    assert_eq!(fs::read(&path).unwrap(), b"Hi");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn input_comes_from_a_file_or_string() {
+    let cat = ",[.,]";
+    let path = temp_path("input");
+    fs::write(&path, b"from a file").unwrap();
+    let output = run(&["--input", path.to_str().unwrap(), "-c", cat], b"stdin");
+    assert_eq!(output.stdout, b"from a file");
+    fs::remove_file(path).unwrap();
+    let output = run(&["--input-string", "inline", "-c", cat], b"stdin");
+    assert_eq!(output.stdout, b"inline");
+}
+
+#[test]
+fn dump_tape_shows_the_cells() {
+    let output = run(&["--dump-tape", "-c", "+++>++"], b"");
+    assert!(stderr(&output).starts_with("00000000  03 02 "));
+    assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn repl_keeps_the_tape_between_lines() {
+    let output = run(&["--repl"], b"+++>++\n<.>.\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"bf> bf> \x03\x02bf> \n");
+}
+
+#[test]
+fn source_file_may_contain_invalid_utf8() {
+    let path = temp_path("invalid-utf8.bf");
+    fs::write(&path, b"+++\xff\xfe.").unwrap();
+    let output = run(&[path.to_str().unwrap()], b"");
+    fs::remove_file(path).unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, [3]);
+}
+
+#[test]
+fn watch_reports_each_change() {
+    let output = run(&["-O", "0", "--watch", "0", "-c", "+-"], b"");
+    assert_eq!(
+        stderr(&output).lines().collect::<Vec<_>>(),
+        [
+            "watch: cell 0 changed from 0 to 1 at instruction 0",
+            "watch: cell 0 changed from 1 to 0 at instruction 1",
+        ]
+    );
+    assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn time_goes_to_stderr() {
+    let output = run(&["--time", "-c", HI], b"");
+    assert_eq!(output.stdout, b"Hi");
+    let err = stderr(&output);
+    assert!(err.starts_with("Parsed in "), "{}", err);
+    assert!(err.contains("Executed in "), "{}", err);
+}
+
+#[test]
+fn debug_output_stays_off_stdout() {
+    let output = run(&["--debug", "-x", "-c", "+#."], b"");
+    assert_eq!(output.stdout, [1]);
+    assert!(stderr(&output).contains("#: ptr=0 | [1]"));
+    let output = run(&["--debug", "--quiet", "-x", "-c", "+#."], b"");
+    assert_eq!(
+        (&output.stdout[..], &output.stderr[..]),
+        (&[1][..], &b""[..])
+    );
+}
+
+#[test]
+fn split_on_separates_program_from_input() {
+    let output = run(&["--split-on", "!"], b",+.,+.!AB");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"BC");
+}
+
+#[test]
+fn bang_starts_the_input() {
+    let output = run(&["--bang", "-c", ",+.!A"], b"stdin");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"B");
+}
+
+#[test]
+fn check_reports_unbalanced_brackets() {
+    let output = run(&["--check", "-c", "+[-].,"], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run(&["--check", "-c", "+[-"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Unmatched left bracket at line 1, column 2"));
+    assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn exit_code_is_cell_0() {
+    let output = run(&["--exit-code", "-c", "+++>+"], b"");
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn newline_at_end_is_added_once() {
+    let output = run(&["--newline-at-end", "-c", HI], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"Hi\n");
+    let output = run(&["--newline-at-end", "-c", "++++++++++."], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"\n");
+}
+
+#[test]
+fn classic_dialect_has_30000_cells() {
+    let last = format!("{}+", ">".repeat(29999));
+    let output = run(&["--dialect", "classic", "-c", &last], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let past = format!("{}>", last);
+    let output = run(&["--dialect", "classic", "-c", &past], b"");
+    assert!(stderr(&output).contains("Pointer out of bounds: cell 30000"));
+    let output = run(
+        &["--dialect", "classic", "--tape-size", "40000", "-c", &past],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn stats_as_json() {
+    let output = run(&["--stats", "--json", "-c", "++."], b"");
+    assert_eq!(output.stdout, [2]);
+    let err = stderr(&output);
+    assert!(err.starts_with("{\"stats\":{\"steps\":2,"), "{}", err);
+    assert!(err.trim_end().ends_with('}'), "{}", err);
+}
+
+#[test]
+fn repl_shows_and_resets_the_pointer() {
+    let output = run(&["--repl"], b">>>\n:ptr\n:reset\n:ptr\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"bf> bf> 3\nbf> bf> 0\nbf> \n");
+}
+
+#[test]
+fn bench_reports_its_samples() {
+    let output = run(&["--bench", "3", "-c", HI], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"");
+    assert!(stderr(&output).starts_with("3 samples: min "));
+}
+
+#[test]
+fn cache_dir_is_reused() {
+    let dir = temp_path("cache");
+    let args = |code| ["--cache-dir", dir.to_str().unwrap(), "-c", code];
+    let entries = || fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path());
+    assert_eq!(run(&args(HI), b"").stdout, b"Hi");
+    let hi: Vec<_> = entries().collect();
+    assert_eq!(hi.len(), 1);
+    assert_eq!(run(&args("+."), b"").stdout, [1]);
+    let one = entries().find(|path| *path != hi[0]).unwrap();
+    // Swapping the entries shows the second run of HI loads its entry
+    fs::copy(one, &hi[0]).unwrap();
+    let output = run(&args(HI), b"");
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.stdout, [1]);
+}
+
+#[test]
+fn command_fragments_form_one_program() {
+    let output = run(&["-c", "+++[", "-c", ">++<-]>."], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, [6]);
+}